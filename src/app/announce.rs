@@ -0,0 +1,89 @@
+use super::envelope::{Envelope, Kind};
+use crate::{
+    config::{Settings, load},
+    transport::{
+        address::{Address, local_addresses},
+        client::{self, Delivery},
+    },
+};
+use anyhow::Result;
+use std::{sync::Arc, time::Duration};
+use tokio::sync::RwLock;
+
+/// Сообщает известным устройствам, что этот адрес сменился.
+/// Внутри зашифрован id устройства — так получатель убеждается, что ключ тот же,
+/// а подписанное время отправки не даёт прислать тот же announce позже.
+pub async fn announce(config: &Settings) -> Result<Vec<Delivery>> {
+    let envelope = Envelope {
+        kind: Kind::Announce,
+        ..Envelope::new(config.server.port, &config.device_id)
+    }
+    .seal(&config.device_id, &config.key)?;
+    let peers: Vec<String> = config
+        .connections
+        .iter()
+        .map(|connection| Address::new(&connection.host, connection.port).to_string())
+        .collect();
+    Ok(client::fan_out(
+        &client::build(&config.client)?,
+        &peers,
+        &envelope.to_string(),
+    )
+    .await)
+}
+
+/// Следит за адресами интерфейсов, пока работает `bufsy run` на порту `port`.
+/// `[network]` читается из общих настроек на каждом шаге, так что SIGHUP меняет и его;
+/// `interface` из `bufsy run --interface` важнее конфига.
+pub async fn watch_loop(
+    config_dir: String,
+    settings: Arc<RwLock<Settings>>,
+    port: u16,
+    interface: Option<String>,
+) {
+    let mut known = local_addresses(
+        interface
+            .as_deref()
+            .or(settings.read().await.network.interface.as_deref()),
+    );
+    loop {
+        let mut network = settings.read().await.network.clone();
+        network.interface = interface.clone().or(network.interface);
+        if network.watch_interval == 0 {
+            // слежение выключено, но его могут включить без перезапуска
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            continue;
+        }
+        tokio::time::sleep(Duration::from_secs(network.watch_interval)).await;
+        let current = local_addresses(network.interface.as_deref());
+        if current == known {
+            continue;
+        }
+        tracing::info!(?known, ?current, "Network changed");
+        known = current;
+        if known.is_empty() {
+            continue;
+        }
+        // connections могли пополниться с момента запуска
+        let mut config = match load(&config_dir) {
+            Ok(config) => config,
+            Err(e) => {
+                tracing::warn!(error = %e, "Failed to announce");
+                continue;
+            }
+        };
+        config.server.port = port;
+        match announce(&config).await {
+            Ok(report) => {
+                for delivery in report.iter().filter(|d| d.error.is_some()) {
+                    tracing::warn!(
+                        peer = delivery.peer,
+                        error = delivery.error.as_deref().unwrap_or_default(),
+                        "Failed to announce"
+                    );
+                }
+            }
+            Err(e) => tracing::warn!(error = %e, "Failed to announce"),
+        }
+    }
+}
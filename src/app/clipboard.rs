@@ -0,0 +1,50 @@
+use anyhow::Result;
+use std::io::Read;
+use wl_clipboard_rs::{
+    copy::{self, Options, Source},
+    paste::{self, ClipboardType, Seat, get_contents},
+};
+
+/// Кладёт текст в буфер обмена Wayland
+pub fn copy(text: &str) -> Result<()> {
+    Options::new().copy(
+        Source::Bytes(text.as_bytes().into()),
+        copy::MimeType::Autodetect,
+    )?;
+    Ok(())
+}
+
+/// Текст из буфера обмена; `None` — буфер пуст или в нём не текст
+pub fn paste() -> Result<Option<String>> {
+    match get_contents(
+        ClipboardType::Regular,
+        Seat::Unspecified,
+        paste::MimeType::Text,
+    ) {
+        Ok((mut pipe, _)) => {
+            let mut contents = vec![];
+            pipe.read_to_end(&mut contents)?;
+            Ok(Some(String::from_utf8_lossy(&contents).to_string()))
+        }
+        Err(paste::Error::NoSeats)
+        | Err(paste::Error::ClipboardEmpty)
+        | Err(paste::Error::NoMimeType) => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Менеджеры паролей (KeePassXC и другие) помечают секреты типом `x-kde-passwordManagerHint`
+pub fn is_secret() -> bool {
+    get_contents(
+        ClipboardType::Regular,
+        Seat::Unspecified,
+        paste::MimeType::Specific("x-kde-passwordManagerHint"),
+    )
+    .ok()
+    .and_then(|(mut pipe, _)| {
+        let mut hint = String::new();
+        pipe.read_to_string(&mut hint).ok()?;
+        Some(hint.trim() == "secret")
+    })
+    .unwrap_or(false)
+}
@@ -1,9 +1,11 @@
 use aes_gcm::aead::rand_core::RngCore;
 use aes_gcm::{
     Aes256Gcm, Nonce,
-    aead::{Aead, KeyInit, OsRng},
+    aead::{Aead, KeyInit, OsRng, Payload},
 };
 use hex;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
 
 /// Генерирует случайный ключ AES-256 (32 байта)
 pub fn generate_key() -> [u8; 32] {
@@ -19,7 +21,35 @@ pub fn generate_nonce() -> [u8; 12] {
     nonce
 }
 
+/// Короткий отпечаток ключа: по нему устройства узнают друг друга, не раскрывая сам ключ
+pub fn fingerprint(key_hex: &str) -> String {
+    let digest = Sha256::digest(key_hex.as_bytes());
+    hex::encode(&digest[..8])
+}
+
+/// HMAC-SHA256 от `data` на общем ключе: доказывает знание ключа, не раскрывая и не
+/// шифруя заново сами данные
+pub fn mac(key_hex: &str, data: &str) -> Result<String, String> {
+    let key_bytes =
+        hex::decode(key_hex).map_err(|e| format!("Ошибка декодирования ключа: {}", e))?;
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&key_bytes)
+        .map_err(|e| format!("Ошибка ключа HMAC: {}", e))?;
+    mac.update(data.as_bytes());
+    Ok(hex::encode(mac.finalize().into_bytes()))
+}
+
 pub fn encrypt(plaintext: &str, key_hex: &str, nonce_hex: &str) -> Result<String, String> {
+    encrypt_with(plaintext, key_hex, nonce_hex, "")
+}
+
+/// Шифрует сообщение и подписывает `aad`: открытые данные, которые нельзя подменить,
+/// не сломав расшифровку
+pub fn encrypt_with(
+    plaintext: &str,
+    key_hex: &str,
+    nonce_hex: &str,
+    aad: &str,
+) -> Result<String, String> {
     // Декодируем key и nonce из hex
     let key_bytes =
         hex::decode(key_hex).map_err(|e| format!("Ошибка декодирования ключа: {}", e))?;
@@ -42,12 +72,18 @@ pub fn encrypt(plaintext: &str, key_hex: &str, nonce_hex: &str) -> Result<String
 
     // Создаем cipher
     let key = aes_gcm::Key::<Aes256Gcm>::from_slice(&key_bytes);
-    let cipher = Aes256Gcm::new(&key);
+    let cipher = Aes256Gcm::new(key);
     let nonce = Nonce::from_slice(&nonce_bytes);
 
     // Шифруем
     let ciphertext = cipher
-        .encrypt(nonce, plaintext.as_bytes())
+        .encrypt(
+            nonce,
+            Payload {
+                msg: plaintext.as_bytes(),
+                aad: aad.as_bytes(),
+            },
+        )
         .map_err(|e| format!("Ошибка шифрования: {:?}", e))?;
 
     // Возвращаем только ciphertext в hex
@@ -56,6 +92,16 @@ pub fn encrypt(plaintext: &str, key_hex: &str, nonce_hex: &str) -> Result<String
 
 /// Дешифрует сообщение с AES-256-GCM
 pub fn decrypt(key_hex: &str, nonce_hex: &str, ciphertext_hex: &str) -> Result<String, String> {
+    decrypt_with(key_hex, nonce_hex, ciphertext_hex, "")
+}
+
+/// Дешифрует сообщение, зашифрованное `encrypt_with` с теми же `aad`
+pub fn decrypt_with(
+    key_hex: &str,
+    nonce_hex: &str,
+    ciphertext_hex: &str,
+    aad: &str,
+) -> Result<String, String> {
     // Декодируем из hex
     let key_bytes =
         hex::decode(key_hex).map_err(|e| format!("Ошибка декодирования ключа: {}", e))?;
@@ -80,12 +126,18 @@ pub fn decrypt(key_hex: &str, nonce_hex: &str, ciphertext_hex: &str) -> Result<S
 
     // Создаем cipher
     let key = aes_gcm::Key::<Aes256Gcm>::from_slice(&key_bytes);
-    let cipher = Aes256Gcm::new(&key);
+    let cipher = Aes256Gcm::new(key);
     let nonce = Nonce::from_slice(&nonce_bytes);
 
     // Дешифруем
     let plaintext = cipher
-        .decrypt(nonce, ciphertext.as_ref())
+        .decrypt(
+            nonce,
+            Payload {
+                msg: &ciphertext,
+                aad: aad.as_bytes(),
+            },
+        )
         .map_err(|e| format!("Ошибка дешифрования: {:?}", e))?;
 
     // Конвертируем в строку
@@ -121,6 +173,32 @@ mod tests {
         assert_ne!(ciphertext_hex, message);
     }
 
+    #[test]
+    fn associated_data() {
+        let key_hex = hex::encode(generate_key());
+        let nonce_hex = hex::encode(generate_nonce());
+
+        let ciphertext_hex = encrypt_with("кот", &key_hex, &nonce_hex, "9296|kind=ping").unwrap();
+        assert_eq!(
+            decrypt_with(&key_hex, &nonce_hex, &ciphertext_hex, "9296|kind=ping").unwrap(),
+            "кот"
+        );
+        decrypt_with(&key_hex, &nonce_hex, &ciphertext_hex, "9296").unwrap_err();
+        decrypt(&key_hex, &nonce_hex, &ciphertext_hex).unwrap_err();
+    }
+
+    #[test]
+    fn mac_keyed() {
+        let key_hex = hex::encode(generate_key());
+        assert_eq!(mac(&key_hex, "кот").unwrap(), mac(&key_hex, "кот").unwrap());
+        assert_eq!(mac(&key_hex, "кот").unwrap().len(), 64);
+        assert_ne!(mac(&key_hex, "кот").unwrap(), mac(&key_hex, "кит").unwrap());
+        assert_ne!(
+            mac(&key_hex, "кот").unwrap(),
+            mac(&hex::encode(generate_key()), "кот").unwrap()
+        );
+    }
+
     #[test]
     fn generate_len() {
         assert_eq!(hex::encode(generate_key()).len(), 64);
@@ -133,6 +211,17 @@ mod tests {
         assert_ne!(hex::encode(generate_nonce()), hex::encode(generate_nonce()));
     }
 
+    #[test]
+    fn fingerprint_stable() {
+        let key_hex = hex::encode(generate_key());
+        assert_eq!(fingerprint(&key_hex), fingerprint(&key_hex));
+        assert_eq!(fingerprint(&key_hex).len(), 16);
+        assert_ne!(
+            fingerprint(&key_hex),
+            fingerprint(&hex::encode(generate_key()))
+        );
+    }
+
     #[test]
     fn incorrectkeysize() {
         let key_hex =
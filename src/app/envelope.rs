@@ -0,0 +1,353 @@
+use super::encryption::{decrypt_with, encrypt_with, generate_nonce};
+use super::outbox::now;
+use super::sync::Hlc;
+use anyhow::{Result, anyhow};
+use std::fmt;
+
+/// Сколько секунд служебное сообщение (announce, запросы) считается свежим: старше — это
+/// повтор перехваченного сообщения или часы устройств сильно расходятся
+pub const MAX_AGE: u64 = 300;
+
+/// Сообщение в сети: `ciphertext|nonce[|port[|key=value...]]`
+///
+/// Первые три поля позиционные, всё остальное передаётся парами `key=value`,
+/// неизвестные ключи игнорируются. Порт и известные поля подписаны ключом как associated
+/// data AES-GCM: подменённое, дописанное или убранное поле ломает расшифровку.
+/// Версии до подписи полей шлют только `ciphertext|nonce|port` без подписи, такой
+/// конверт открывается как есть, но его порту верить нельзя.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Envelope {
+    pub ciphertext: String,
+    pub nonce: String,
+    pub port: Option<u16>,
+    pub device: Option<String>,
+    pub kind: Kind,
+    /// секрет (пароль): получатель не сохраняет его в истории
+    pub sensitive: bool,
+    /// устройство, на котором текст скопировали (`bufsy run --sync`)
+    pub origin: Option<String>,
+    /// метка копирования для выбора последней записи
+    pub clock: Option<Hlc>,
+    /// сколько ещё раз получатели могут переслать сообщение дальше (`[gossip]`)
+    pub hops: Option<u8>,
+    /// id исходного сообщения, если это пересланная копия
+    pub forwarded: Option<String>,
+    /// время отправки, секунды unix
+    pub sent: Option<u64>,
+}
+
+/// Тип сообщения; без поля `kind` это текст для буфера обмена
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Kind {
+    #[default]
+    Text,
+    /// устройство сменило адрес, буфер обмена не трогаем
+    Announce,
+    /// запрос и ответ `/status`, внутри путь `/status` или JSON состояния
+    Status,
+    /// проверка связи и ключа, получатель отвечает `Pong` с HMAC этого текста
+    Ping,
+    Pong,
+    /// запрос и ответ `/latest` и `/history`, внутри путь запроса или JSON записей
+    Pull,
+}
+
+impl Kind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Kind::Text => "text",
+            Kind::Announce => "announce",
+            Kind::Status => "status",
+            Kind::Ping => "ping",
+            Kind::Pong => "pong",
+            Kind::Pull => "pull",
+        }
+    }
+
+    fn parse(kind: &str) -> Result<Self> {
+        match kind {
+            "text" => Ok(Kind::Text),
+            "announce" => Ok(Kind::Announce),
+            "status" => Ok(Kind::Status),
+            "ping" => Ok(Kind::Ping),
+            "pong" => Ok(Kind::Pong),
+            "pull" => Ok(Kind::Pull),
+            _ => Err(anyhow!("Unknown message kind {}", kind)),
+        }
+    }
+}
+
+impl Envelope {
+    /// Текст от устройства `device`, которое слушает `port`; остальные поля
+    /// заполняются до `seal`
+    pub fn new(port: u16, device: &str) -> Self {
+        Self {
+            port: Some(port),
+            device: Some(device.to_string()).filter(|d| !d.is_empty()),
+            sent: Some(now()),
+            ..Default::default()
+        }
+    }
+
+    /// Шифрует сообщение со свежим nonce и подписывает поля конверта
+    pub fn seal(self, message: &str, key: &str) -> Result<Self> {
+        let nonce = hex::encode(generate_nonce());
+        Ok(Self {
+            ciphertext: encrypt_with(message, key, &nonce, &self.header())
+                .map_err(|e| anyhow!(e))?,
+            nonce,
+            ..self
+        })
+    }
+
+    /// id сообщения: nonce, а у пересланной копии — nonce исходного сообщения.
+    /// Подменить его, не сломав расшифровку, нельзя.
+    pub fn id(&self) -> &str {
+        self.forwarded.as_deref().unwrap_or(&self.nonce)
+    }
+
+    /// Отправлено не раньше `MAX_AGE` секунд назад (и не позже, чем через столько же)
+    pub fn is_fresh(&self) -> bool {
+        self.sent
+            .is_some_and(|sent| sent.abs_diff(now()) <= MAX_AGE)
+    }
+
+    pub fn open(&self, key: &str) -> Result<String> {
+        let header = self.header();
+        decrypt_with(key, &self.nonce, &self.ciphertext, &header)
+            .or_else(|e| {
+                // старый отправитель: кроме порта полей нет, и подписи тоже
+                match self.port {
+                    Some(port) if header == port.to_string() => {
+                        decrypt_with(key, &self.nonce, &self.ciphertext, "")
+                    }
+                    _ => Err(e),
+                }
+            })
+            .map_err(|e| anyhow!(e))
+    }
+
+    /// Всё, что идёт после nonce, в одном и том же порядке — это и подписывается
+    fn header(&self) -> String {
+        let Some(port) = self.port else {
+            return String::new();
+        };
+        let mut header = port.to_string();
+        if let Some(device) = &self.device {
+            header.push_str(&format!("|device={}", device));
+        }
+        if self.kind != Kind::Text {
+            header.push_str(&format!("|kind={}", self.kind.as_str()));
+        }
+        if self.sensitive {
+            header.push_str("|sensitive=1");
+        }
+        if let Some(origin) = &self.origin {
+            header.push_str(&format!("|origin={}", origin));
+        }
+        if let Some(clock) = self.clock {
+            header.push_str(&format!("|clock={}", clock));
+        }
+        if let Some(hops) = self.hops {
+            header.push_str(&format!("|hops={}", hops));
+        }
+        if let Some(forwarded) = &self.forwarded {
+            header.push_str(&format!("|forwarded={}", forwarded));
+        }
+        if let Some(sent) = self.sent {
+            header.push_str(&format!("|sent={}", sent));
+        }
+        header
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let parts: Vec<&str> = text.split('|').collect();
+        if parts.len() < 2 || parts[0].is_empty() || parts[1].is_empty() {
+            return Err(anyhow!("Invalid input"));
+        }
+
+        let mut envelope = Envelope {
+            ciphertext: parts[0].to_string(),
+            nonce: parts[1].to_string(),
+            ..Default::default()
+        };
+        if let Some(port) = parts.get(2) {
+            envelope.port = Some(port.parse::<u16>()?);
+        }
+        for field in parts.iter().skip(3) {
+            let (key, value) = field
+                .split_once('=')
+                .ok_or_else(|| anyhow!("Invalid field {}", field))?;
+            match key {
+                "device" => envelope.device = Some(value.to_string()),
+                "kind" => envelope.kind = Kind::parse(value)?,
+                "sensitive" => envelope.sensitive = value == "1",
+                "origin" => envelope.origin = Some(value.to_string()),
+                "clock" => envelope.clock = Some(value.parse()?),
+                "hops" => envelope.hops = Some(value.parse()?),
+                "forwarded" => envelope.forwarded = Some(value.to_string()),
+                "sent" => envelope.sent = Some(value.parse()?),
+                _ => {}
+            }
+        }
+        Ok(envelope)
+    }
+}
+
+impl fmt::Display for Envelope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}|{}", self.ciphertext, self.nonce)?;
+        if self.port.is_some() {
+            write!(f, "|{}", self.header())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::encryption::generate_key;
+
+    #[test]
+    fn seal_open() {
+        let key = hex::encode(generate_key());
+        let envelope = Envelope::new(9296, "abc").seal("кот :>", &key).unwrap();
+        let parsed = Envelope::parse(&envelope.to_string()).unwrap();
+
+        assert_eq!(parsed, envelope);
+        assert_eq!(parsed.port, Some(9296));
+        assert_eq!(parsed.device, Some("abc".to_string()));
+        assert_eq!(parsed.open(&key).unwrap(), "кот :>");
+    }
+
+    #[test]
+    fn parse_legacy() {
+        let envelope = Envelope::parse("aa|bb").unwrap();
+        assert_eq!(envelope.port, None);
+        let envelope = Envelope::parse("aa|bb|9296").unwrap();
+        assert_eq!(envelope.port, Some(9296));
+        assert_eq!(envelope.device, None);
+        let envelope = Envelope::parse("aa|bb|9296|device=cat|hops=2").unwrap();
+        assert_eq!(envelope.device, Some("cat".to_string()));
+        assert_eq!(envelope.kind, Kind::Text);
+        assert_eq!(envelope.hops, Some(2));
+        let envelope = Envelope::parse("aa|bb|9296|device=cat|color=red").unwrap();
+        assert_eq!(envelope.hops, None);
+    }
+
+    #[test]
+    fn kind_roundtrip() {
+        let key = hex::encode(generate_key());
+        let envelope = Envelope {
+            kind: Kind::Announce,
+            ..Envelope::new(9296, "abc")
+        }
+        .seal("", &key)
+        .unwrap();
+        assert!(envelope.to_string().contains("|device=abc|kind=announce|"));
+        assert_eq!(Envelope::parse(&envelope.to_string()).unwrap(), envelope);
+        assert!(Envelope::parse("aa|bb|9296|kind=cat").is_err());
+
+        let secret = Envelope {
+            sensitive: true,
+            ..envelope
+        }
+        .seal("", &key)
+        .unwrap();
+        assert_eq!(Envelope::parse(&secret.to_string()).unwrap(), secret);
+
+        let synced = Envelope {
+            origin: Some("abc".to_string()),
+            clock: Some("1700000000000.2".parse().unwrap()),
+            ..secret
+        }
+        .seal("", &key)
+        .unwrap();
+        assert!(
+            synced
+                .to_string()
+                .contains("|origin=abc|clock=1700000000000.2|")
+        );
+        assert_eq!(Envelope::parse(&synced.to_string()).unwrap(), synced);
+        assert!(Envelope::parse("aa|bb|9296|clock=now").is_err());
+    }
+
+    #[test]
+    fn signed_fields() {
+        let key = hex::encode(generate_key());
+        let envelope = Envelope {
+            kind: Kind::Announce,
+            sensitive: true,
+            hops: Some(1),
+            ..Envelope::new(9296, "abc")
+        }
+        .seal("abc", &key)
+        .unwrap();
+        let wire = envelope.to_string();
+        assert_eq!(Envelope::parse(&wire).unwrap().open(&key).unwrap(), "abc");
+
+        // любое подменённое, дописанное или убранное поле не расшифровывается
+        for forged in [
+            wire.replace("|9296|", "|9297|"),
+            wire.replace("device=abc", "device=cat"),
+            wire.replace("|kind=announce", ""),
+            wire.replace("|sensitive=1", ""),
+            wire.replace("hops=1", "hops=255"),
+            format!("{}|origin=cat", wire),
+            format!("{}|sent=1", wire),
+        ] {
+            assert!(
+                Envelope::parse(&forged).unwrap().open(&key).is_err(),
+                "{}",
+                forged
+            );
+        }
+        // неизвестные поля не подписываются и не мешают
+        let extended = Envelope::parse(&format!("{}|color=red", wire)).unwrap();
+        assert_eq!(extended.open(&key).unwrap(), "abc");
+    }
+
+    #[test]
+    fn legacy_sender() {
+        let key = hex::encode(generate_key());
+        let nonce = hex::encode(generate_nonce());
+        let ciphertext = encrypt_with("кот", &key, &nonce, "").unwrap();
+        let legacy = Envelope::parse(&format!("{}|{}|9296", ciphertext, nonce)).unwrap();
+        assert_eq!(legacy.open(&key).unwrap(), "кот");
+
+        // подписанный конверт, у которого убрали поля, старым не прикидывается
+        let sealed = Envelope::new(9296, "abc").seal("кот", &key).unwrap();
+        let stripped = Envelope {
+            device: None,
+            sent: None,
+            ..sealed
+        };
+        assert!(stripped.open(&key).is_err());
+    }
+
+    #[test]
+    fn freshness() {
+        let envelope = Envelope::new(9296, "abc");
+        assert!(envelope.is_fresh());
+        let stale = Envelope {
+            sent: Some(now() - MAX_AGE - 1),
+            ..envelope.clone()
+        };
+        assert!(!stale.is_fresh());
+        let unsent = Envelope {
+            sent: None,
+            ..envelope
+        };
+        assert!(!unsent.is_fresh());
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert!(Envelope::parse("").is_err());
+        assert!(Envelope::parse("|").is_err());
+        assert!(Envelope::parse("aa|").is_err());
+        assert!(Envelope::parse("aa|bb|port").is_err());
+        assert!(Envelope::parse("aa|bb|9296|device").is_err());
+    }
+}
@@ -0,0 +1,83 @@
+use super::envelope::Envelope;
+use anyhow::Result;
+use std::{future::Future, pin::Pin, sync::Arc};
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Принятое сообщение: конверт уже разобран, расшифрован и не повтор
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub envelope: Envelope,
+    /// расшифрованный текст
+    pub text: String,
+    /// адрес отправителя без порта
+    pub peer: String,
+}
+
+/// Обработчик принятых сообщений `bufsy run`: буфер обмена, уведомления, адреса устройств
+pub trait MessageHandler: Send + Sync {
+    fn handle<'a>(&'a self, message: &'a Message) -> BoxFuture<'a, Result<()>>;
+}
+
+/// Обработчики по порядку; первая ошибка прерывает цепочку
+#[derive(Default, Clone)]
+pub struct Chain(Vec<Arc<dyn MessageHandler>>);
+
+impl Chain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, handler: impl MessageHandler + 'static) -> Self {
+        self.0.push(Arc::new(handler));
+        self
+    }
+}
+
+impl MessageHandler for Chain {
+    fn handle<'a>(&'a self, message: &'a Message) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            for handler in &self.0 {
+                handler.handle(message).await?;
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    struct Log(&'static str, Arc<Mutex<Vec<&'static str>>>);
+
+    impl MessageHandler for Log {
+        fn handle<'a>(&'a self, _message: &'a Message) -> BoxFuture<'a, Result<()>> {
+            Box::pin(async move {
+                self.1.lock().unwrap().push(self.0);
+                match self.0 {
+                    "fail" => Err(anyhow::anyhow!("failed")),
+                    _ => Ok(()),
+                }
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn chain_stops_on_error() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let chain = Chain::new()
+            .with(Log("first", log.clone()))
+            .with(Log("fail", log.clone()))
+            .with(Log("last", log.clone()));
+        let message = Message {
+            envelope: Envelope::default(),
+            text: "cat".to_string(),
+            peer: "127.0.0.1".to_string(),
+        };
+
+        assert!(chain.handle(&message).await.is_err());
+        assert_eq!(*log.lock().unwrap(), ["first", "fail"]);
+    }
+}
@@ -0,0 +1,393 @@
+use super::{
+    encryption::{decrypt, encrypt, generate_key, generate_nonce},
+    outbox::now,
+};
+use crate::config::{Settings, settings::HistorySettings};
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{ErrorKind, Write},
+    os::{fd::AsRawFd, unix::fs::OpenOptionsExt},
+};
+
+/// MIME-тип текста, другие типы bufsy пока не передаёт
+pub const MIME_TEXT: &str = "text/plain;charset=utf-8";
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+/// Запись истории буфера обмена
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Item {
+    pub id: u64,
+    /// unix-время
+    pub time: u64,
+    pub direction: Direction,
+    /// откуда пришло или кому ушло
+    pub peer: String,
+    pub mime: String,
+    pub text: String,
+    /// помечено как секрет (пароль), в списке не показывается
+    #[serde(default)]
+    pub sensitive: bool,
+}
+
+/// История в `history` рядом с конфигом: по строке `nonce|ciphertext` на запись,
+/// зашифрованной ключом из `history.key`. Этот ключ никуда не отправляется, в отличие
+/// от общего ключа сети в конфиге.
+pub struct History {
+    path: String,
+    /// ключ сети: им шифровалась история до появления `history.key`
+    legacy_key: String,
+    settings: HistorySettings,
+}
+
+/// Содержимое файла истории
+struct Stored {
+    items: Vec<Item>,
+    /// записи, ещё зашифрованные ключом сети; при следующей записи шифруются заново
+    legacy: usize,
+    /// строки, которые не открывает ни один ключ (например, после `bufsy key set`
+    /// на старой истории): не показываются, но и не удаляются
+    unreadable: Vec<String>,
+}
+
+impl History {
+    pub fn open(config_dir: &str, config: &Settings) -> Self {
+        Self {
+            path: format!("{}/history", config_dir),
+            legacy_key: config.key.clone(),
+            settings: config.history.clone(),
+        }
+    }
+
+    /// Добавляет запись и выбрасывает вышедшие за лимиты; `None` — запись не сохранена
+    pub fn record(
+        &self,
+        direction: Direction,
+        peer: &str,
+        text: &str,
+        sensitive: bool,
+    ) -> Result<Option<u64>> {
+        if !self.settings.enabled || (sensitive && self.settings.exclude_sensitive) {
+            return Ok(None);
+        }
+        let _lock = self.lock()?;
+        let stored = self.read()?;
+        let mut items = stored.items;
+        let id = self.next_id(&items)?;
+        items.push(Item {
+            id,
+            time: now(),
+            direction,
+            peer: peer.to_string(),
+            mime: MIME_TEXT.to_string(),
+            text: text.to_string(),
+            sensitive,
+        });
+        let before = items.len();
+        self.retain(&mut items, now());
+        if items.len() == before && stored.legacy == 0 {
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+            file.write_all(self.seal(items.last().unwrap())?.as_bytes())?;
+        } else {
+            self.write(&stored.unreadable, &items)?;
+        }
+        Ok(Some(id))
+    }
+
+    /// Записи от старых к новым
+    pub fn list(&self) -> Result<Vec<Item>> {
+        let mut items = self.read()?.items;
+        self.retain(&mut items, now());
+        Ok(items)
+    }
+
+    /// Записи, в тексте или адресе которых есть `query`, без учёта регистра
+    pub fn search(&self, query: &str) -> Result<Vec<Item>> {
+        let query = query.to_lowercase();
+        Ok(self
+            .list()?
+            .into_iter()
+            .filter(|item| {
+                item.text.to_lowercase().contains(&query)
+                    || item.peer.to_lowercase().contains(&query)
+            })
+            .collect())
+    }
+
+    pub fn get(&self, id: u64) -> Result<Item> {
+        self.list()?
+            .into_iter()
+            .find(|item| item.id == id)
+            .ok_or_else(|| anyhow!("No history item {}", id))
+    }
+
+    /// Удаляет всю историю, возвращает число записей
+    pub fn clear(&self) -> Result<usize> {
+        let _lock = self.lock()?;
+        let count = self.read()?.items.len();
+        match fs::remove_file(&self.path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(count),
+        }
+    }
+
+    /// id не повторяются ни после `clear`, ни когда старые записи уходят по лимитам:
+    /// на них ссылаются строки `history --menu` и `/history?since=N`. Счётчик лежит
+    /// в `history.seq` и меняется под той же блокировкой, что и история.
+    fn next_id(&self, items: &[Item]) -> Result<u64> {
+        let path = format!("{}.seq", self.path);
+        let stored = match fs::read_to_string(&path) {
+            Ok(content) => content.trim().parse().unwrap_or_default(),
+            Err(e) if e.kind() == ErrorKind::NotFound => 0,
+            Err(e) => return Err(e.into()),
+        };
+        let id = items.last().map_or(1, |item| item.id + 1).max(stored);
+        fs::write(&path, (id + 1).to_string())?;
+        Ok(id)
+    }
+
+    /// Лимиты по возрасту и количеству, 0 — без ограничения
+    fn retain(&self, items: &mut Vec<Item>, now: u64) {
+        if self.settings.max_age_days > 0 {
+            let max_age = self.settings.max_age_days * 24 * 60 * 60;
+            items.retain(|item| now.saturating_sub(item.time) < max_age);
+        }
+        if self.settings.max_items > 0 && items.len() > self.settings.max_items {
+            items.drain(..items.len() - self.settings.max_items);
+        }
+    }
+
+    fn read(&self) -> Result<Stored> {
+        let mut stored = Stored {
+            items: Vec::new(),
+            legacy: 0,
+            unreadable: Vec::new(),
+        };
+        let content = match fs::read_to_string(&self.path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(stored),
+            Err(e) => return Err(e.into()),
+        };
+        let key = self.key()?;
+        for line in content.lines().filter(|line| !line.is_empty()) {
+            let open = |key: &str| {
+                let (nonce, ciphertext) = line.split_once('|')?;
+                let json = decrypt(key, nonce, ciphertext).ok()?;
+                serde_json::from_str::<Item>(&json).ok()
+            };
+            if let Some(item) = open(&key) {
+                stored.items.push(item);
+            } else if let Some(item) = open(&self.legacy_key) {
+                stored.items.push(item);
+                stored.legacy += 1;
+            } else {
+                stored.unreadable.push(line.to_string());
+            }
+        }
+        if !stored.unreadable.is_empty() {
+            tracing::warn!(
+                lines = stored.unreadable.len(),
+                path = self.path,
+                "History lines do not open with the history key, kept as is"
+            );
+        }
+        Ok(stored)
+    }
+
+    /// Через временный файл: прерванная запись не портит историю
+    fn write(&self, unreadable: &[String], items: &[Item]) -> Result<()> {
+        let tmp = format!("{}.tmp", self.path);
+        let mut content = String::new();
+        for line in unreadable {
+            content.push_str(line);
+            content.push('\n');
+        }
+        for item in items {
+            content.push_str(&self.seal(item)?);
+        }
+        fs::write(&tmp, content)?;
+        fs::rename(tmp, &self.path)?;
+        Ok(())
+    }
+
+    fn seal(&self, item: &Item) -> Result<String> {
+        let nonce = hex::encode(generate_nonce());
+        let ciphertext =
+            encrypt(&serde_json::to_string(item)?, &self.key()?, &nonce).map_err(|e| anyhow!(e))?;
+        Ok(format!("{}|{}\n", nonce, ciphertext))
+    }
+
+    /// Ключ истории из `history.key`. При первом обращении пишется во временный файл
+    /// с правами 0600 и появляется под своим именем целиком, даже если его одновременно
+    /// создают демон и команда.
+    fn key(&self) -> Result<String> {
+        let path = format!("{}.key", self.path);
+        match fs::read_to_string(&path) {
+            Ok(key) => return Ok(key.trim().to_string()),
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+            Err(_) => {}
+        }
+        let tmp = format!("{}.{}", path, std::process::id());
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&tmp)?
+            .write_all(hex::encode(generate_key()).as_bytes())?;
+        let linked = fs::hard_link(&tmp, &path);
+        fs::remove_file(&tmp)?;
+        match linked {
+            Err(e) if e.kind() != ErrorKind::AlreadyExists => Err(e.into()),
+            _ => Ok(fs::read_to_string(&path)?.trim().to_string()),
+        }
+    }
+
+    /// Пишут и демон, и команды: изменения идут по очереди под `flock`
+    fn lock(&self) -> Result<File> {
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(format!("{}.lock", self.path))?;
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        Ok(file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::load_config::tests::test_load_config;
+
+    fn history(config_dir: &str, settings: HistorySettings) -> History {
+        fs::create_dir_all(config_dir).unwrap();
+        let mut config = test_load_config();
+        config.history = settings;
+        let history = History::open(config_dir, &config);
+        history.clear().unwrap();
+        // счётчик id от прошлого запуска тестов
+        let _ = fs::remove_file(format!("{}.seq", history.path));
+        history
+    }
+
+    #[test]
+    fn record_and_search() {
+        let history = history("test_dir/history", HistorySettings::default());
+        history
+            .record(Direction::Sent, "[::1]:9296", "Кот :>", false)
+            .unwrap();
+        history
+            .record(Direction::Received, "10.0.0.2", "dog", false)
+            .unwrap();
+        assert_eq!(
+            history
+                .record(Direction::Sent, "10.0.0.2", "hunter2", true)
+                .unwrap(),
+            None
+        );
+
+        let content = fs::read_to_string("test_dir/history/history").unwrap();
+        assert!(!content.contains("dog"));
+        let items = history.list().unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(history.search("кот").unwrap()[0].id, 1);
+        assert_eq!(history.search("10.0.0").unwrap()[0].text, "dog");
+        assert_eq!(history.get(2).unwrap().direction, Direction::Received);
+        assert!(history.get(3).is_err());
+
+        // история шифруется своим ключом, а не ключом сети
+        let mut config = test_load_config();
+        config.key = "0".repeat(64);
+        assert_eq!(
+            History::open("test_dir/history", &config)
+                .list()
+                .unwrap()
+                .len(),
+            2
+        );
+
+        assert_eq!(history.clear().unwrap(), 2);
+        assert!(history.list().unwrap().is_empty());
+        // после очистки id продолжаются, а не начинаются заново
+        assert_eq!(
+            history
+                .record(Direction::Sent, "10.0.0.2", "cat", false)
+                .unwrap(),
+            Some(3)
+        );
+    }
+
+    #[test]
+    fn retention_limits() {
+        let history = history(
+            "test_dir/history_limits",
+            HistorySettings {
+                max_items: 3,
+                exclude_sensitive: false,
+                ..Default::default()
+            },
+        );
+        for text in ["a", "b", "c", "d", "e"] {
+            history
+                .record(Direction::Sent, "[::1]:9296", text, text == "e")
+                .unwrap();
+        }
+        let items = history.list().unwrap();
+        assert_eq!(
+            items.iter().map(|item| item.id).collect::<Vec<_>>(),
+            [3, 4, 5]
+        );
+        assert!(items[2].sensitive);
+
+        let mut items = items;
+        items[0].time = now() - 31 * 24 * 60 * 60;
+        history.retain(&mut items, now());
+        assert_eq!(items.len(), 2);
+    }
+
+    #[test]
+    fn foreign_lines() {
+        let history = history("test_dir/history_keys", HistorySettings::default());
+        let config = test_load_config();
+        let item = Item {
+            id: 1,
+            time: now(),
+            direction: Direction::Sent,
+            peer: "10.0.0.2".to_string(),
+            mime: MIME_TEXT.to_string(),
+            text: "old".to_string(),
+            sensitive: false,
+        };
+        // запись старой версии, зашифрованная ключом сети, и строка под чужим ключом
+        let nonce = hex::encode(generate_nonce());
+        let legacy = encrypt(&serde_json::to_string(&item).unwrap(), &config.key, &nonce).unwrap();
+        let foreign = format!("{}|{}", nonce, "00".repeat(40));
+        fs::write(
+            &history.path,
+            format!("{}\n{}|{}\n", foreign, nonce, legacy),
+        )
+        .unwrap();
+
+        assert_eq!(history.list().unwrap(), [item]);
+        history
+            .record(Direction::Sent, "10.0.0.2", "new", false)
+            .unwrap();
+        let content = fs::read_to_string(&history.path).unwrap();
+        assert!(content.starts_with(&foreign));
+        assert!(!content.contains(&legacy));
+        assert_eq!(history.list().unwrap().len(), 2);
+    }
+}
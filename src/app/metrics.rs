@@ -0,0 +1,137 @@
+use crate::config::Settings;
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+/// Счётчики `bufsy run` для `/metrics`
+#[derive(Default)]
+pub struct Metrics {
+    /// все запросы на `/text`
+    pub received: AtomicU64,
+    /// новые сообщения, обработанные без ошибок
+    pub accepted: AtomicU64,
+    pub duplicate: AtomicU64,
+    /// не расшифровались ключом этого устройства
+    pub decrypt_failed: AtomicU64,
+    /// битый конверт или ошибка обработки
+    pub rejected: AtomicU64,
+    pub bytes_in: AtomicU64,
+    pub bytes_out: AtomicU64,
+    /// неудачные отправки по устройствам из `connections`, остальные адреса — `other`
+    send_failures: Mutex<BTreeMap<String, u64>>,
+}
+
+impl Metrics {
+    pub fn inc(counter: &AtomicU64, by: u64) {
+        counter.fetch_add(by, Ordering::Relaxed);
+    }
+
+    /// Метка — имя или id устройства: адреса из `send -i`, announce и пересылок меняются,
+    /// и серий по ним за долгую работу набралось бы без счёта
+    pub fn send_failed(&self, config: &Settings, peer: &str) {
+        let label = config
+            .find_connection(peer)
+            .ok()
+            .map(|index| &config.connections[index])
+            .and_then(|c| c.name.clone().or(c.id.clone()))
+            .unwrap_or_else(|| "other".to_string());
+        if let Ok(mut failures) = self.send_failures.lock() {
+            *failures.entry(label).or_default() += 1;
+        }
+    }
+
+    /// Текстовый формат Prometheus; `seen` — размер кэша повторов
+    pub fn render(&self, seen: usize) -> String {
+        let mut out = String::new();
+        for (name, help, counter) in [
+            ("received", "Envelopes posted to /text", &self.received),
+            ("accepted", "New messages handled", &self.accepted),
+            ("duplicate", "Messages already seen", &self.duplicate),
+            (
+                "decrypt_failed",
+                "Messages not decrypted with our key",
+                &self.decrypt_failed,
+            ),
+            ("rejected", "Malformed or failed messages", &self.rejected),
+        ] {
+            let _ = writeln!(out, "# HELP bufsy_messages_{}_total {}", name, help);
+            let _ = writeln!(out, "# TYPE bufsy_messages_{}_total counter", name);
+            let _ = writeln!(
+                out,
+                "bufsy_messages_{}_total {}",
+                name,
+                counter.load(Ordering::Relaxed)
+            );
+        }
+        for (name, help, counter) in [
+            ("in", "Bytes received on /text", &self.bytes_in),
+            ("out", "Bytes of envelopes sent", &self.bytes_out),
+        ] {
+            let _ = writeln!(out, "# HELP bufsy_bytes_{}_total {}", name, help);
+            let _ = writeln!(out, "# TYPE bufsy_bytes_{}_total counter", name);
+            let _ = writeln!(
+                out,
+                "bufsy_bytes_{}_total {}",
+                name,
+                counter.load(Ordering::Relaxed)
+            );
+        }
+
+        out.push_str("# HELP bufsy_send_failures_total Failed deliveries per configured peer\n");
+        out.push_str("# TYPE bufsy_send_failures_total counter\n");
+        if let Ok(failures) = self.send_failures.lock() {
+            for (peer, count) in failures.iter() {
+                let _ = writeln!(
+                    out,
+                    "bufsy_send_failures_total{{peer=\"{}\"}} {}",
+                    label(peer),
+                    count
+                );
+            }
+        }
+
+        out.push_str("# HELP bufsy_dedup_cache_size Envelope hashes kept to drop repeats\n");
+        out.push_str("# TYPE bufsy_dedup_cache_size gauge\n");
+        let _ = writeln!(out, "bufsy_dedup_cache_size {}", seen);
+        out
+    }
+}
+
+fn label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::load_config::tests::test_load_config;
+
+    #[test]
+    fn render_counters() {
+        let metrics = Metrics::default();
+        Metrics::inc(&metrics.received, 2);
+        Metrics::inc(&metrics.bytes_in, 120);
+        let mut config = test_load_config();
+        config.new_connection("fe80::1%eth0", 9296).name = Some("laptop".to_string());
+        metrics.send_failed(&config, "[fe80::1%eth0]:9296");
+        metrics.send_failed(&config, "[fe80::1%eth0]:9296");
+        metrics.send_failed(&config, "10.0.0.2:9296");
+        metrics.send_failed(&config, "10.0.0.3:9296");
+
+        let text = metrics.render(5);
+        assert!(text.contains("bufsy_messages_received_total 2\n"));
+        assert!(text.contains("bufsy_messages_duplicate_total 0\n"));
+        assert!(text.contains("bufsy_bytes_in_total 120\n"));
+        assert!(text.contains("bufsy_send_failures_total{peer=\"laptop\"} 2\n"));
+        assert!(text.contains("bufsy_send_failures_total{peer=\"other\"} 2\n"));
+        assert!(text.contains("bufsy_dedup_cache_size 5\n"));
+    }
+}
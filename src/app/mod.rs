@@ -1,6 +1,14 @@
+pub mod announce;
+pub mod clipboard;
 pub mod encryption;
-mod fnt;
+pub mod envelope;
+pub mod handler;
+pub mod history;
+pub mod metrics;
+pub mod outbox;
 pub mod print;
+pub mod receive;
+pub mod session;
+pub mod sync;
 
-pub use fnt::fnthost;
 pub use print::print;
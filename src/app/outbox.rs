@@ -0,0 +1,363 @@
+use crate::{
+    config::{
+        Settings,
+        settings::{ClientSettings, OutboxSettings},
+    },
+    transport::client,
+};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File, OpenOptions},
+    os::fd::AsRawFd,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{sync::RwLock, task::JoinSet};
+
+/// Конверты, которые не удалось доставить, хранятся в `outbox.toml` рядом с конфигом
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+pub struct Outbox {
+    #[serde(default)]
+    pub items: Vec<OutboxItem>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct OutboxItem {
+    /// `host:port` получателя
+    pub peer: String,
+    pub envelope: String,
+    /// unix-время постановки в очередь
+    pub created: u64,
+    pub attempts: u32,
+    /// unix-время следующей попытки
+    pub next_attempt: u64,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct FlushReport {
+    pub delivered: usize,
+    pub failed: usize,
+    pub expired: usize,
+    /// получатель отказался, повторять бесполезно
+    pub rejected: usize,
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn outbox_path(config_dir: &str) -> String {
+    format!("{}/outbox.toml", config_dir)
+}
+
+impl Outbox {
+    pub fn load(config_dir: &str) -> Result<Self> {
+        match fs::read_to_string(outbox_path(config_dir)) {
+            Ok(content) => Ok(toml::from_str(&content)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Очередь меняют и демон, и команды: load → изменение → save идут по очереди под
+    /// `flock`, иначе `outbox clear` или новый элемент откатились бы чужим save.
+    pub async fn lock(config_dir: &str) -> Result<File> {
+        let path = format!("{}.lock", outbox_path(config_dir));
+        tokio::task::spawn_blocking(move || {
+            let file = OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(path)?;
+            if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+            Ok(file)
+        })
+        .await?
+    }
+
+    /// Через временный файл: прерванная запись не портит очередь
+    pub fn save(&self, config_dir: &str) -> Result<()> {
+        let path = outbox_path(config_dir);
+        let tmp = format!("{}.tmp", path);
+        fs::write(&tmp, toml::to_string_pretty(self)?)?;
+        fs::rename(tmp, path)?;
+        Ok(())
+    }
+
+    pub fn push(&mut self, peer: &str, envelope: &str, now: u64) {
+        self.items.push(OutboxItem {
+            peer: peer.to_string(),
+            envelope: envelope.to_string(),
+            created: now,
+            attempts: 0,
+            next_attempt: now,
+        });
+    }
+
+    /// Удаляет элементы старше `ttl` секунд, возвращает их количество
+    pub fn expire(&mut self, now: u64, ttl: u64) -> usize {
+        let before = self.items.len();
+        self.items
+            .retain(|item| now.saturating_sub(item.created) < ttl);
+        before - self.items.len()
+    }
+
+    /// Доставляет элементы, у которых подошло время (или все, если `force`).
+    /// Под блокировкой только чтение очереди и запись итогов: сама доставка идёт без неё,
+    /// и `bufsy send` не ждёт таймаутов недоступных устройств. Итоги находят свой элемент
+    /// по получателю и конверту, так что добавленное или удалённое за это время не теряется
+    /// и не возвращается, а элемент, уже доставленный другим flush, просто пропускается.
+    pub async fn flush(
+        config_dir: &str,
+        client: &reqwest::Client,
+        settings: &OutboxSettings,
+        force: bool,
+    ) -> Result<FlushReport> {
+        let now = now();
+        let mut report = FlushReport::default();
+        let due: Vec<OutboxItem> = {
+            let _lock = Self::lock(config_dir).await?;
+            let mut outbox = Self::load(config_dir)?;
+            report.expired = outbox.expire(now, settings.ttl);
+            if report.expired > 0 {
+                outbox.save(config_dir)?;
+            }
+            outbox
+                .items
+                .into_iter()
+                .filter(|item| force || item.next_attempt <= now)
+                .collect()
+        };
+        if due.is_empty() {
+            return Ok(report);
+        }
+
+        // один конверт обычно ждёт нескольких получателей: им он уходит одним fan_out
+        let mut envelopes: Vec<(&str, Vec<String>)> = Vec::new();
+        for item in &due {
+            match envelopes
+                .iter_mut()
+                .find(|(envelope, _)| *envelope == item.envelope)
+            {
+                Some((_, peers)) => peers.push(item.peer.clone()),
+                None => envelopes.push((&item.envelope, vec![item.peer.clone()])),
+            }
+        }
+        let mut tasks = JoinSet::new();
+        for (envelope, peers) in envelopes {
+            let client = client.clone();
+            let envelope = envelope.to_string();
+            tasks.spawn(async move {
+                let report = client::fan_out(&client, &peers, &envelope).await;
+                (envelope, report)
+            });
+        }
+        let results = tasks.join_all().await;
+
+        let _lock = Self::lock(config_dir).await?;
+        let mut outbox = Self::load(config_dir)?;
+        for (envelope, deliveries) in &results {
+            for delivery in deliveries {
+                let index = outbox
+                    .items
+                    .iter()
+                    .position(|item| item.peer == delivery.peer && item.envelope == *envelope);
+                if delivery.status.is_delivered() {
+                    report.delivered += 1;
+                } else if !delivery.status.is_retryable() {
+                    report.rejected += 1;
+                } else {
+                    report.failed += 1;
+                    if let Some(index) = index {
+                        let item = &mut outbox.items[index];
+                        item.attempts += 1;
+                        item.next_attempt = now + settings.backoff(item.attempts);
+                    }
+                    continue;
+                }
+                if let Some(index) = index {
+                    outbox.items.remove(index);
+                }
+            }
+        }
+        outbox.save(config_dir)?;
+        Ok(report)
+    }
+}
+
+/// Повторяет доставку, пока работает `bufsy run`. `[outbox]` и `[client]` берутся из
+/// общих настроек перед каждой попыткой, так что SIGHUP меняет и их.
+pub async fn retry_loop(config_dir: String, settings: Arc<RwLock<Settings>>) {
+    let mut client: Option<(ClientSettings, reqwest::Client)> = None;
+    loop {
+        let (outbox_settings, client_settings) = {
+            let settings = settings.read().await;
+            (settings.outbox.clone(), settings.client.clone())
+        };
+        tokio::time::sleep(Duration::from_secs(outbox_settings.retry_min.max(1))).await;
+        if client
+            .as_ref()
+            .is_none_or(|(timeouts, _)| *timeouts != client_settings)
+        {
+            // не собрался — пробуем снова в следующий раз, пока с прежним клиентом
+            match client::build(&client_settings) {
+                Ok(built) => client = Some((client_settings, built)),
+                Err(e) => tracing::error!(error = %e, "Failed to build HTTP client"),
+            }
+        }
+        let Some((_, client)) = &client else {
+            continue;
+        };
+        let report = match Outbox::flush(&config_dir, client, &outbox_settings, false).await {
+            Ok(report) => report,
+            Err(e) => {
+                tracing::error!(error = %e, "Failed to flush outbox");
+                continue;
+            }
+        };
+        if report != FlushReport::default() {
+            tracing::info!(
+                delivered = report.delivered,
+                failed = report.failed,
+                expired = report.expired,
+                rejected = report.rejected,
+                "Outbox flushed"
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expire_ttl() {
+        let mut outbox = Outbox::default();
+        outbox.push("127.0.0.1:9296", "aa|bb|9296", 100);
+        outbox.push("127.0.0.1:9296", "cc|dd|9296", 200);
+
+        assert_eq!(outbox.expire(250, 100), 1);
+        assert_eq!(outbox.items.len(), 1);
+        assert_eq!(outbox.items[0].envelope, "cc|dd|9296");
+    }
+
+    #[tokio::test]
+    async fn lock_is_exclusive() {
+        let config_dir = "test_dir/outbox_lock";
+        fs::create_dir_all(config_dir).unwrap();
+        let first = Outbox::lock(config_dir).await.unwrap();
+        let second = tokio::spawn(Outbox::lock(config_dir));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!second.is_finished());
+        drop(first);
+        second.await.unwrap().unwrap();
+    }
+
+    #[test]
+    fn backoff_grows_and_caps() {
+        let settings = OutboxSettings {
+            ttl: 86400,
+            retry_min: 5,
+            retry_max: 60,
+        };
+        assert_eq!(settings.backoff(1), 5);
+        assert_eq!(settings.backoff(2), 10);
+        assert_eq!(settings.backoff(3), 20);
+        assert_eq!(settings.backoff(5), 60);
+        assert_eq!(settings.backoff(100), 60);
+    }
+
+    #[tokio::test]
+    async fn flush_unreachable() {
+        let config_dir = "test_dir/outbox_flush";
+        fs::create_dir_all(config_dir).unwrap();
+        let settings = OutboxSettings {
+            ttl: 86400,
+            retry_min: 5,
+            retry_max: 60,
+        };
+        let mut outbox = Outbox::default();
+        outbox.push("127.0.0.1:1", "aa|bb|9296", now());
+        outbox.push("127.0.0.1:1", "cc|dd|9296", now() - 100000);
+        outbox.save(config_dir).unwrap();
+
+        let report = Outbox::flush(config_dir, &reqwest::Client::new(), &settings, false)
+            .await
+            .unwrap();
+        assert_eq!(
+            report,
+            FlushReport {
+                delivered: 0,
+                failed: 1,
+                expired: 1,
+                rejected: 0
+            }
+        );
+        let outbox = Outbox::load(config_dir).unwrap();
+        assert_eq!(outbox.items[0].attempts, 1);
+        assert!(outbox.items[0].next_attempt > now());
+    }
+
+    #[tokio::test]
+    async fn flush_without_lock() {
+        let config_dir = "test_dir/outbox_flush_lock";
+        fs::create_dir_all(config_dir).unwrap();
+        // принимает соединение и молчит, пока клиент не сдастся по таймауту
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let peer = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let _held = listener.accept().await;
+            tokio::time::sleep(Duration::from_secs(10)).await;
+        });
+        let mut outbox = Outbox::default();
+        outbox.push(&peer, "aa|bb|9296", now());
+        outbox.save(config_dir).unwrap();
+
+        let client = client::build(&ClientSettings {
+            connect_timeout_ms: 500,
+            timeout_ms: 500,
+        })
+        .unwrap();
+        let flush = tokio::spawn(async move {
+            Outbox::flush(config_dir, &client, &Default::default(), true).await
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        // пока идёт доставка, очередь можно менять
+        let lock = tokio::time::timeout(Duration::from_millis(100), Outbox::lock(config_dir))
+            .await
+            .unwrap()
+            .unwrap();
+        let mut outbox = Outbox::load(config_dir).unwrap();
+        outbox.push(&peer, "cc|dd|9296", now());
+        outbox.save(config_dir).unwrap();
+        drop(lock);
+
+        assert_eq!(flush.await.unwrap().unwrap().failed, 1);
+        let outbox = Outbox::load(config_dir).unwrap();
+        assert_eq!(outbox.items.len(), 2);
+        assert_eq!(outbox.items[0].attempts, 1);
+        assert_eq!(outbox.items[1].attempts, 0);
+    }
+
+    #[test]
+    fn save_load() {
+        let config_dir = "test_dir/outbox";
+        fs::create_dir_all(config_dir).unwrap();
+        let mut outbox = Outbox::default();
+        outbox.push("127.0.0.1:9296", "aa|bb|9296", 100);
+
+        outbox.save(config_dir).unwrap();
+        assert_eq!(Outbox::load(config_dir).unwrap(), outbox);
+        assert_eq!(
+            Outbox::load("test_dir/outbox_missing").unwrap(),
+            Outbox::default()
+        );
+    }
+}
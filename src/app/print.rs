@@ -3,7 +3,7 @@ use notify_rust::Notification;
 
 pub fn print(text: &str) -> Result<()> {
     Notification::new().summary("Bufsy").body(text).show()?;
-    println!("LOG {}", text);
+    tracing::trace!(text, "Notification shown");
     Ok(())
 }
 
@@ -0,0 +1,407 @@
+use super::clipboard::copy;
+use super::envelope::{Envelope, Kind};
+use super::handler::{BoxFuture, Chain, Message, MessageHandler};
+use super::history::{Direction, History};
+use super::print;
+use super::session::Session;
+use crate::{
+    config::{Settings, load, lock, save},
+    transport::{address::Address, client},
+};
+use anyhow::Result;
+use std::sync::Arc;
+
+/// Обработчики `bufsy run` по умолчанию: адрес отправителя, буфер обмена, уведомление,
+/// история и пересылка. С `sync` в буфер обмена попадает только запись новее текущей.
+pub fn handlers(config_dir: &str, sync: Option<Arc<Session>>) -> Chain {
+    chain(config_dir, sync, Chain::new().with(Clipboard).with(Notify))
+}
+
+/// История и пересылка идут после `apply` и сами не падают: ошибка цепочки значит,
+/// что повторять их нечего, и отправитель может прислать сообщение ещё раз
+fn chain(config_dir: &str, sync: Option<Arc<Session>>, apply: Chain) -> Chain {
+    let chain = Chain::new().with(Peers {
+        config_dir: config_dir.to_string(),
+    });
+    let chain = match sync {
+        Some(session) => chain.with(LastWriter {
+            session,
+            then: apply,
+        }),
+        None => chain.with(apply),
+    };
+    chain
+        .with(Record {
+            config_dir: config_dir.to_string(),
+        })
+        .with(Forward {
+            config_dir: config_dir.to_string(),
+        })
+}
+
+/// Конфиг с диска: его могли поменять после запуска (ключ, лимиты, connections).
+/// Битый файл не мешает принять сообщение, этот шаг просто пропускается.
+fn fresh(config_dir: &str) -> Option<Settings> {
+    load(config_dir)
+        .inspect_err(|e| tracing::warn!(error = %e, "Failed to load config"))
+        .ok()
+}
+
+/// Запоминает новый адрес устройства; announce без своего id внутри отклоняется
+pub struct Peers {
+    pub config_dir: String,
+}
+
+impl MessageHandler for Peers {
+    fn handle<'a>(&'a self, message: &'a Message) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let envelope = &message.envelope;
+            if envelope.kind == Kind::Announce
+                && envelope.device.as_deref() != Some(message.text.as_str())
+            {
+                return Err(anyhow::anyhow!("Invalid announce"));
+            }
+            let Some(port) = envelope.port else {
+                return Ok(());
+            };
+            let config_dir = self.config_dir.clone();
+            let device = envelope.device.clone();
+            let peer = message.peer.clone();
+            // `flock` ждёт, пока команда допишет конфиг, поэтому не в потоке tokio
+            tokio::task::spawn_blocking(move || {
+                let _lock = lock(&config_dir)?;
+                let Some(mut config) = fresh(&config_dir) else {
+                    return Ok(());
+                };
+                if config.upsert_connection(device.as_deref(), &peer, port) {
+                    save(&config, &config_dir)?;
+                }
+                Ok(())
+            })
+            .await?
+        })
+    }
+}
+
+/// Пересылает принятый текст своим устройствам, пока у сообщения остаются `hops`
+pub struct Forward {
+    pub config_dir: String,
+}
+
+impl MessageHandler for Forward {
+    fn handle<'a>(&'a self, message: &'a Message) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let Some(config) = fresh(&self.config_dir) else {
+                return Ok(());
+            };
+            let Some((peers, body)) = forward(&config, message) else {
+                return Ok(());
+            };
+            // отправитель не ждёт, пока ответят устройства дальше по цепочке
+            tokio::spawn(async move {
+                let client = match client::build(&config.client) {
+                    Ok(client) => client,
+                    Err(e) => {
+                        tracing::warn!(error = %e, "Failed to forward");
+                        return;
+                    }
+                };
+                for delivery in client::fan_out(&client, &peers, &body).await {
+                    tracing::debug!(
+                        peer = delivery.peer,
+                        status = ?delivery.status,
+                        error = delivery.error,
+                        "Forwarded"
+                    );
+                }
+            });
+            Ok(())
+        })
+    }
+}
+
+/// Адреса и конверт для пересылки: текст, зашифрованный заново с id исходного сообщения
+/// (по нему получатели отбрасывают повторы), отправитель — это устройство, `hops` на один меньше.
+/// Тому, от кого пришло сообщение, и устройству-источнику оно не пересылается.
+pub fn forward(config: &Settings, message: &Message) -> Option<(Vec<String>, String)> {
+    let envelope = &message.envelope;
+    if !config.gossip.enabled || envelope.kind != Kind::Text {
+        return None;
+    }
+    // больше своего `gossip.hops` не пересылаем, что бы ни прислал отправитель
+    let hops = envelope
+        .hops
+        .map(|hops| hops.min(config.gossip.hops))
+        .filter(|&hops| hops > 0)?;
+    let origin = envelope.origin.clone().or(envelope.device.clone());
+    let peers: Vec<String> = config
+        .connections
+        .iter()
+        .filter(|c| !c.disabled && c.host != message.peer && (c.id.is_none() || c.id != origin))
+        .map(|c| Address::new(&c.host, c.port).to_string())
+        .collect();
+    if peers.is_empty() {
+        return None;
+    }
+    let envelope = Envelope {
+        sensitive: envelope.sensitive,
+        origin,
+        clock: envelope.clock,
+        hops: Some(hops - 1),
+        forwarded: Some(envelope.id().to_string()),
+        ..Envelope::new(config.server.port, &config.device_id)
+    }
+    .seal(&message.text, &config.key)
+    .inspect_err(|e| tracing::warn!(error = %e, "Failed to forward"))
+    .ok()?;
+    Some((peers, envelope.to_string()))
+}
+
+/// Сохраняет принятый текст в истории
+pub struct Record {
+    pub config_dir: String,
+}
+
+impl MessageHandler for Record {
+    fn handle<'a>(&'a self, message: &'a Message) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            if message.envelope.kind != Kind::Text {
+                return Ok(());
+            }
+            let Some(config) = fresh(&self.config_dir) else {
+                return Ok(());
+            };
+            let history = History::open(&self.config_dir, &config);
+            if let Err(e) = history.record(
+                Direction::Received,
+                &message.peer,
+                &message.text,
+                message.envelope.sensitive,
+            ) {
+                tracing::warn!(error = %e, "Failed to save history");
+            }
+            Ok(())
+        })
+    }
+}
+
+/// `bufsy run --sync`: передаёт дальше только текст новее текущей записи, повторы
+/// и проигравшие одновременные копии молча пропускаются
+pub struct LastWriter {
+    pub session: Arc<Session>,
+    pub then: Chain,
+}
+
+impl MessageHandler for LastWriter {
+    fn handle<'a>(&'a self, message: &'a Message) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let envelope = &message.envelope;
+            if envelope.kind != Kind::Text {
+                return Ok(());
+            }
+            let origin = envelope
+                .origin
+                .as_deref()
+                .or(envelope.device.as_deref())
+                .unwrap_or(&message.peer);
+            if !self
+                .session
+                .sync
+                .accept(origin, envelope.clock, &message.text)
+            {
+                tracing::debug!(origin, clock = ?envelope.clock, "Older than the clipboard, skipped");
+                return Ok(());
+            }
+            self.then.handle(message).await
+        })
+    }
+}
+
+/// Копирует принятый текст в буфер обмена
+pub struct Clipboard;
+
+impl MessageHandler for Clipboard {
+    fn handle<'a>(&'a self, message: &'a Message) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            if message.envelope.kind != Kind::Text {
+                return Ok(());
+            }
+            copy(&message.text)?;
+            tracing::info!(peer = message.peer, bytes = message.text.len(), "Copied");
+            tracing::trace!(text = message.text, "Copied");
+            Ok(())
+        })
+    }
+}
+
+/// Уведомление на рабочем столе с принятым текстом
+pub struct Notify;
+
+impl MessageHandler for Notify {
+    fn handle<'a>(&'a self, message: &'a Message) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            if message.envelope.kind == Kind::Text {
+                print(&message.text)?;
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::load_config::tests::test_load_config;
+
+    #[tokio::test]
+    async fn announce_moves_device() {
+        let config_dir = "test_dir/announce";
+        std::fs::create_dir_all(config_dir).unwrap();
+        let mut config = test_load_config();
+        config.upsert_connection(Some("d1e5c0de"), "192.168.1.10", 9296);
+        save(&config, config_dir).unwrap();
+
+        let peers = Peers {
+            config_dir: config_dir.to_string(),
+        };
+        let announce = Envelope {
+            kind: Kind::Announce,
+            ..Envelope::new(9296, "d1e5c0de")
+        }
+        .seal("d1e5c0de", &config.key)
+        .unwrap();
+        let message = Message {
+            envelope: announce.clone(),
+            text: "d1e5c0de".to_string(),
+            peer: "10.0.0.7".to_string(),
+        };
+        peers.handle(&message).await.unwrap();
+
+        let connections = load(config_dir).unwrap().connections;
+        assert_eq!(connections.len(), 1);
+        assert_eq!(connections[0].host, "10.0.0.7");
+
+        let forged = Message {
+            envelope: announce,
+            text: "cafe".to_string(),
+            peer: "10.0.0.8".to_string(),
+        };
+        assert!(peers.handle(&forged).await.is_err());
+    }
+
+    struct Fail;
+
+    impl MessageHandler for Fail {
+        fn handle<'a>(&'a self, _message: &'a Message) -> BoxFuture<'a, Result<()>> {
+            Box::pin(async move { Err(anyhow::anyhow!("No clipboard")) })
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_apply_has_no_side_effects() {
+        let config_dir = "test_dir/receive_order";
+        std::fs::create_dir_all(config_dir).unwrap();
+        let config = test_load_config();
+        save(&config, config_dir).unwrap();
+        let history = History::open(config_dir, &config);
+        history.clear().unwrap();
+
+        let message = Message {
+            envelope: Envelope::new(9296, "laptop")
+                .seal("cat", &config.key)
+                .unwrap(),
+            text: "cat".to_string(),
+            peer: "10.0.0.7".to_string(),
+        };
+        // повтор после такой ошибки не должен дать вторую запись в истории
+        let failing = chain(config_dir, None, Chain::new().with(Fail));
+        assert!(failing.handle(&message).await.is_err());
+        assert!(history.list().unwrap().is_empty());
+
+        chain(config_dir, None, Chain::new())
+            .handle(&message)
+            .await
+            .unwrap();
+        assert_eq!(history.list().unwrap().len(), 1);
+    }
+
+    struct Count(Arc<std::sync::Mutex<usize>>);
+
+    impl MessageHandler for Count {
+        fn handle<'a>(&'a self, _message: &'a Message) -> BoxFuture<'a, Result<()>> {
+            Box::pin(async move {
+                *self.0.lock().unwrap() += 1;
+                Ok(())
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn sync_applies_once() {
+        let count = Arc::new(std::sync::Mutex::new(0));
+        let writer = LastWriter {
+            session: Arc::new(Session::new()),
+            then: Chain::new().with(Count(count.clone())),
+        };
+        let key = test_load_config().key;
+        let clock = Some("1700000000000.0".parse().unwrap());
+        let message = Message {
+            envelope: Envelope {
+                origin: Some("laptop".to_string()),
+                clock,
+                ..Envelope::new(9296, "laptop")
+            }
+            .seal("cat", &key)
+            .unwrap(),
+            text: "cat".to_string(),
+            peer: "10.0.0.7".to_string(),
+        };
+        writer.handle(&message).await.unwrap();
+        writer.handle(&message).await.unwrap();
+        assert_eq!(*count.lock().unwrap(), 1);
+        assert!(writer.session.sync.is_current("cat"));
+    }
+
+    #[test]
+    fn forward_to_others() {
+        let mut config = test_load_config();
+        config.gossip.enabled = true;
+        config.upsert_connection(Some("a"), "10.0.0.7", 9296);
+        config.upsert_connection(Some("laptop"), "10.0.0.8", 9296);
+        config.upsert_connection(None, "10.0.0.9", 9296);
+        let envelope = Envelope {
+            origin: Some("laptop".to_string()),
+            hops: Some(2),
+            ..Envelope::new(9296, "a")
+        }
+        .seal("cat", &config.key)
+        .unwrap();
+        let mut message = Message {
+            envelope: envelope.clone(),
+            text: "cat".to_string(),
+            peer: "10.0.0.7".to_string(),
+        };
+
+        let (peers, body) = forward(&config, &message).unwrap();
+        assert_eq!(peers, ["10.0.0.9:9296"]);
+        let forwarded = Envelope::parse(&body).unwrap();
+        assert_eq!(forwarded.id(), envelope.id());
+        assert_eq!(forwarded.device.as_deref(), Some(config.device_id.as_str()));
+        assert_eq!(forwarded.origin.as_deref(), Some("laptop"));
+        assert_eq!(forwarded.hops, Some(1));
+        assert_eq!(forwarded.open(&config.key).unwrap(), "cat");
+
+        message.envelope.hops = Some(255);
+        let (_, body) = forward(&config, &message).unwrap();
+        assert_eq!(
+            Envelope::parse(&body).unwrap().hops,
+            Some(config.gossip.hops - 1)
+        );
+
+        message.envelope.hops = Some(0);
+        assert!(forward(&config, &message).is_none());
+        message.envelope.hops = Some(1);
+        config.gossip.enabled = false;
+        assert!(forward(&config, &message).is_none());
+    }
+}
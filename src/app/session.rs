@@ -0,0 +1,65 @@
+use super::{
+    metrics::Metrics,
+    outbox::now,
+    sync::{SyncState, hash},
+};
+use std::collections::HashSet;
+use tokio::sync::RwLock;
+
+/// Состояние работающего `bufsy run`, общее для сервера и управляющего сокета
+pub struct Session {
+    /// unix-время запуска
+    pub started: u64,
+    /// SHA-224 id уже обработанных сообщений
+    seen: RwLock<HashSet<[u8; 28]>>,
+    pub metrics: Metrics,
+    /// часы и текущая запись для `--sync`
+    pub sync: SyncState,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Self {
+            started: now(),
+            seen: RwLock::new(HashSet::with_capacity(312222)),
+            metrics: Metrics::default(),
+            sync: SyncState::default(),
+        }
+    }
+
+    /// Запоминает id сообщения, `false` — такое уже было
+    pub async fn remember(&self, id: &str) -> bool {
+        self.seen.write().await.insert(hash(id))
+    }
+
+    /// Забывает id сообщения, которое не удалось открыть: чужой конверт с подставленным
+    /// nonce не должен закрыть дорогу настоящему
+    pub async fn forget(&self, id: &str) {
+        self.seen.write().await.remove(&hash(id));
+    }
+
+    pub async fn seen(&self) -> usize {
+        self.seen.read().await.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn remember_envelopes() {
+        let session = Session::new();
+        assert!(session.remember("a").await);
+        assert!(!session.remember("a").await);
+        assert_eq!(session.seen().await, 1);
+        session.forget("a").await;
+        assert!(session.remember("a").await);
+    }
+}
@@ -0,0 +1,220 @@
+use anyhow::{Result, anyhow};
+use sha2::{Digest, Sha224};
+use std::{
+    fmt,
+    str::FromStr,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// На сколько миллисекунд метка с другого устройства может опережать наши часы.
+/// Метка из далёкого будущего навсегда выиграла бы у всех следующих копирований.
+pub const MAX_DRIFT: u64 = 60_000;
+
+/// Гибридные логические часы: миллисекунды unix-времени и счётчик событий внутри одной
+/// миллисекунды. Не идут назад, даже если системные часы отстают от часов других устройств.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct Hlc {
+    pub millis: u64,
+    pub counter: u32,
+}
+
+impl Hlc {
+    /// Следующая метка после этой; переполненный счётчик переносится в миллисекунды
+    fn next(self) -> Hlc {
+        match self.counter.checked_add(1) {
+            Some(counter) => Hlc { counter, ..self },
+            None => Hlc {
+                millis: self.millis.saturating_add(1),
+                counter: 0,
+            },
+        }
+    }
+
+    /// Метка локального события
+    fn tick(&mut self, wall: u64) -> Hlc {
+        *self = if wall > self.millis {
+            Hlc {
+                millis: wall,
+                counter: 0,
+            }
+        } else {
+            self.next()
+        };
+        *self
+    }
+
+    /// Учитывает метку с другого устройства: следующие локальные метки будут больше неё
+    fn merge(&mut self, remote: Hlc, wall: u64) {
+        let millis = wall.max(self.millis).max(remote.millis);
+        *self = if millis == self.millis && millis == remote.millis {
+            (*self).max(remote).next()
+        } else if millis == self.millis {
+            self.next()
+        } else if millis == remote.millis {
+            remote.next()
+        } else {
+            Hlc { millis, counter: 0 }
+        };
+    }
+}
+
+impl fmt::Display for Hlc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.millis, self.counter)
+    }
+}
+
+impl FromStr for Hlc {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || anyhow!("Invalid clock {}", s);
+        let (millis, counter) = s.split_once('.').ok_or_else(invalid)?;
+        Ok(Hlc {
+            millis: millis.parse().map_err(|_| invalid())?,
+            counter: counter.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// Состояние `bufsy run --sync`: часы и запись, которая сейчас в буфере обмена.
+///
+/// Побеждает запись с большей парой (метка, устройство-источник), поэтому одновременные
+/// копирования на разных устройствах сходятся к одной и той же записи.
+#[derive(Default)]
+pub struct SyncState(Mutex<State>);
+
+#[derive(Default)]
+struct State {
+    clock: Hlc,
+    /// метка и источник текущей записи
+    current: Option<(Hlc, String)>,
+    /// SHA-224 текста текущей записи
+    text: Option<[u8; 28]>,
+}
+
+impl State {
+    fn stamp(&mut self, origin: &str, text: &str, wall: u64) -> Hlc {
+        let clock = self.clock.tick(wall);
+        self.current = Some((clock, origin.to_string()));
+        self.text = Some(hash(text));
+        clock
+    }
+
+    fn accept(&mut self, origin: &str, clock: Option<Hlc>, text: &str, wall: u64) -> bool {
+        let clock = match clock {
+            Some(clock) if clock.millis > wall.saturating_add(MAX_DRIFT) => {
+                tracing::warn!(origin, %clock, "Clock is too far ahead, skipped");
+                return false;
+            }
+            Some(clock) => {
+                self.clock.merge(clock, wall);
+                clock
+            }
+            // без метки (старая версия или `bufsy send` без демона) — старше любой записи
+            None => Hlc::default(),
+        };
+        let stamp = (clock, origin.to_string());
+        if self
+            .current
+            .as_ref()
+            .is_some_and(|current| &stamp <= current)
+        {
+            return false;
+        }
+        self.current = Some(stamp);
+        self.text = Some(hash(text));
+        true
+    }
+}
+
+impl SyncState {
+    /// Метка для своей записи, которая уходит на другие устройства
+    pub fn stamp(&self, origin: &str, text: &str) -> Hlc {
+        self.0.lock().unwrap().stamp(origin, text, wall())
+    }
+
+    /// `true` — запись новее текущей, её надо положить в буфер обмена. Повтор уже
+    /// принятой записи и проигравшая одновременная копия дают `false`.
+    pub fn accept(&self, origin: &str, clock: Option<Hlc>, text: &str) -> bool {
+        self.0.lock().unwrap().accept(origin, clock, text, wall())
+    }
+
+    /// Текст текущей записи: его появление в буфере обмена не рассылается повторно
+    pub fn is_current(&self, text: &str) -> bool {
+        self.0.lock().unwrap().text == Some(hash(text))
+    }
+}
+
+/// SHA-224 текста: сравнивать буфер обмена, не храня его копию
+pub fn hash(text: &str) -> [u8; 28] {
+    Sha224::digest(text.as_bytes()).into()
+}
+
+fn wall() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clock_order() {
+        let mut clock = Hlc::default();
+        let first = clock.tick(1000);
+        let second = clock.tick(900);
+        assert!(second > first);
+        assert_eq!(second, "1000.1".parse().unwrap());
+
+        clock.merge("5000.3".parse().unwrap(), 1000);
+        assert_eq!(clock.tick(1000).to_string(), "5000.5");
+        assert!("5000".parse::<Hlc>().is_err());
+    }
+
+    #[test]
+    fn last_writer_wins() {
+        let (mut laptop, mut phone) = (State::default(), State::default());
+
+        // копия приходит один раз и не считается новой на принявшем устройстве
+        let clock = laptop.stamp("laptop", "cat", 1000);
+        assert!(phone.accept("laptop", Some(clock), "cat", 1001));
+        assert!(!phone.accept("laptop", Some(clock), "cat", 1002));
+        assert_eq!(phone.text, Some(hash("cat")));
+
+        // одновременные копии: оба устройства выбирают одну
+        let a = laptop.stamp("laptop", "a", 2000);
+        let b = phone.stamp("phone", "b", 2000);
+        assert_eq!(a, b);
+        assert!(laptop.accept("phone", Some(b), "b", 2001));
+        assert!(!phone.accept("laptop", Some(a), "a", 2001));
+        assert_eq!(laptop.current, phone.current);
+
+        // запись без метки старше любой другой и принимается, только пока записей нет
+        assert!(!laptop.accept("10.0.0.2", None, "old bufsy", 3000));
+        assert!(State::default().accept("10.0.0.2", None, "old bufsy", 3000));
+    }
+
+    #[test]
+    fn clock_bounds() {
+        let mut state = State::default();
+        // метка из далёкого будущего не принимается и не двигает часы
+        let future = Hlc {
+            millis: u64::MAX,
+            counter: u32::MAX,
+        };
+        assert!(!state.accept("cat", Some(future), "cat", 1000));
+        assert_eq!(state.clock, Hlc::default());
+
+        // переполненный счётчик переходит в миллисекунды, а не паникует
+        let full = Hlc {
+            millis: 1000,
+            counter: u32::MAX,
+        };
+        assert!(state.accept("cat", Some(full), "cat", 1000));
+        assert_eq!(state.stamp("dog", "dog", 1000), "1001.1".parse().unwrap());
+    }
+}
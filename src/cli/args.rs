@@ -1,22 +1,53 @@
-use super::commands::Commands;
-use crate::{cli::init, config::load};
+use super::commands::{Commands, ConfigEnum, check_config};
+use crate::{
+    cli::{
+        init,
+        logging::{self, LogFormat},
+    },
+    config::upgrade,
+};
 use anyhow::Result;
-use clap::Parser;
+use clap::{ArgAction, Parser};
 
 #[derive(Parser)]
 pub struct Cli {
+    /// подробнее: -v — debug, -vv — trace, с содержимым буфера обмена
+    #[arg(short, long, global = true, action = ArgAction::Count)]
+    verbose: u8,
+    /// тише: -q — только предупреждения, -qq — только ошибки
+    #[arg(short, long, global = true, action = ArgAction::Count, conflicts_with = "verbose")]
+    quiet: u8,
+    /// формат журнала в stderr и в файле
+    #[arg(long, global = true, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
     #[command(subcommand)]
     command: Commands,
 }
 
 impl Cli {
     pub async fn run(&self, config_dir: &str) -> Result<()> {
+        let level = logging::level(self.verbose, self.quiet);
         if (Commands::Init {}) == self.command {
-            init(&config_dir).await?;
+            logging::init(level, self.log_format, None)?;
+            init(config_dir).await?;
             return Ok(());
         }
-        let config = load(&config_dir);
-        self.command.run(&config_dir, config).await?;
+        if let Commands::Config {
+            command: ConfigEnum::Check {},
+        } = self.command
+        {
+            logging::init(level, self.log_format, None)?;
+            return check_config(config_dir);
+        }
+        // `config check` выше только читает, остальные команды обновляют старый конфиг
+        let config = upgrade(config_dir)?;
+        let log_file = format!("{}/bufsy.log", config_dir);
+        logging::init(
+            level,
+            self.log_format,
+            config.log.file.then_some(log_file.as_str()),
+        )?;
+        self.command.run(config_dir, config).await?;
         Ok(())
     }
 }
@@ -1,16 +1,31 @@
 use crate::{
     app::{
-        encryption::{encrypt, generate_nonce},
-        fnthost,
+        clipboard,
+        encryption::fingerprint,
+        envelope::Envelope,
+        history::{Direction, History},
+        metrics::Metrics,
+        outbox::{self, Outbox},
+        session::Session,
     },
     cli::Cli,
-    config::{Settings, load, save, settings::Server},
-    transport::{Backend, axum::ServerAXUM},
+    cli::{
+        daemon, peer, pick,
+        report::{ago, menu_id, menu_line, print_history, print_peers, print_report, print_status},
+        service,
+    },
+    config::{Settings, load, lock, save},
+    transport::{
+        address::Address,
+        client::{self, Delivery},
+        control::{self, Outgoing, Request, Response},
+        mdns::Discovery,
+        pull, status,
+    },
 };
-use anyhow::Result;
+use anyhow::{Result, anyhow};
 use clap::{CommandFactory, Subcommand};
-use std::io::{Read, Write};
-use wl_clipboard_rs::paste::{ClipboardType, MimeType, Seat, get_contents};
+use std::{io::Write, time::Duration};
 
 #[derive(Subcommand, PartialEq)]
 pub enum Commands {
@@ -18,12 +33,27 @@ pub enum Commands {
     Run {
         ip: Option<String>,
         port: Option<u16>,
+        /// слушать только этот интерфейс (eth0, wlan0)
+        #[arg(long)]
+        interface: Option<String>,
+        /// синхронизировать буфер обмена в обе стороны: рассылать свои копирования
+        #[arg(long)]
+        sync: bool,
     },
 
     /// Отправьте буфер
     Send {
         #[arg(short, long, value_name = "ADDRESS")]
         ip: Option<String>,
+        /// только этим устройствам или группам (имя, id или адрес), по умолчанию `default_to.send`
+        #[arg(short, long, value_name = "PEER", value_delimiter = ',')]
+        to: Vec<String>,
+        /// отчёт о доставке в JSON
+        #[arg(long)]
+        json: bool,
+        /// секрет: не сохранять в истории, ни здесь, ни у получателей
+        #[arg(long)]
+        sensitive: bool,
     },
 
     /// перемещение
@@ -33,17 +63,112 @@ pub enum Commands {
         /// адрес
         #[arg(short, long, value_name = "ADDRESS")]
         ip: Option<String>,
+        /// только этим устройствам или группам, по умолчанию `default_to.echo`
+        #[arg(short, long, value_name = "PEER", value_delimiter = ',')]
+        to: Vec<String>,
+        /// отчёт о доставке в JSON
+        #[arg(long)]
+        json: bool,
+        /// секрет: не сохранять в истории, ни здесь, ни у получателей
+        #[arg(long)]
+        sensitive: bool,
+    },
+
+    /// проверка связи и ключа, буфер обмена получателя не меняется
+    Ping {
+        /// адрес, без него — все connections
+        #[arg(short, long, value_name = "ADDRESS")]
+        ip: Option<String>,
+        /// сколько раз
+        #[arg(short, long, default_value_t = 1)]
+        count: u32,
+    },
+
+    /// состояние `bufsy run` и доступность устройств
+    Status {
+        /// вывод в JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// устройства в connections: добавить, переименовать, выключить, проверить
+    Peer {
+        #[command(subcommand)]
+        command: PeerEnum,
+    },
+
+    /// известные устройства
+    Peers {
+        /// вывод в JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// история буфера обмена, без команды — последние записи
+    #[command(args_conflicts_with_subcommands = true)]
+    History {
+        /// строки для dmenu, rofi или fuzzel, новые первыми
+        #[arg(long)]
+        menu: bool,
+        #[command(subcommand)]
+        command: Option<HistoryEnum>,
+    },
+
+    /// забрать с устройства последнюю запись его истории и скопировать
+    Pull {
+        /// адрес устройства
+        peer: String,
+        /// напечатать, а не копировать
+        #[arg(long)]
+        print: bool,
+        /// вместо копирования показать записи устройства с id больше этого
+        #[arg(long, value_name = "ID", conflicts_with = "print")]
+        since: Option<u64>,
+        /// вывод `--since` в JSON
+        #[arg(long, requires = "since")]
+        json: bool,
+    },
+
+    /// выбор из истории: поиск, Enter — скопировать, Tab — отправить на устройство
+    Pick {},
+
+    /// поиск устройств с тем же ключом в локальной сети
+    Discover {
+        /// сколько секунд слушать сеть
+        #[arg(short, long, default_value_t = 3)]
+        timeout: u64,
+        /// добавить найденные устройства в connections
+        #[arg(short, long)]
+        add: bool,
     },
 
     /// инициализация
     Init {},
 
+    /// настройки
+    Config {
+        #[command(subcommand)]
+        command: ConfigEnum,
+    },
+
     /// ключ
     Key {
         #[command(subcommand)]
         command: KeyEnum,
     },
 
+    /// очередь недоставленных сообщений
+    Outbox {
+        #[command(subcommand)]
+        command: OutboxEnum,
+    },
+
+    /// служба systemd
+    Service {
+        #[command(subcommand)]
+        command: ServiceEnum,
+    },
+
     /// генерация завершение
     GenerateCompletion { shells: clap_complete::Shell },
 }
@@ -56,49 +181,343 @@ pub enum KeyEnum {
     Set { key_update: String },
 }
 
+#[derive(Subcommand, PartialEq)]
+pub enum ConfigEnum {
+    /// Проверить config.toml: ключ, порты, адреса устройств, группы
+    Check {},
+}
+
+#[derive(Subcommand, PartialEq)]
+pub enum PeerEnum {
+    /// Все устройства
+    List {
+        /// вывод в JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Добавить устройство
+    Add {
+        /// host:port, IPv6 — [addr]:port
+        address: String,
+        /// имя для `--to`
+        #[arg(short, long)]
+        name: Option<String>,
+    },
+    /// Удалить устройство (имя, id или адрес)
+    Remove { peer: String },
+    /// Дать устройству имя
+    Rename { peer: String, name: String },
+    /// Снова отправлять устройству
+    Enable { peer: String },
+    /// Не отправлять устройству, пока не включат
+    Disable { peer: String },
+    /// Проверить доступность и ключ, без устройства — все
+    Test {
+        peer: Option<String>,
+        /// вывод в JSON
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Subcommand, PartialEq)]
+pub enum OutboxEnum {
+    /// Показать очередь
+    List {},
+    /// Отправить всё сейчас, не дожидаясь повтора
+    Flush {},
+    /// Очистить очередь
+    Clear {
+        /// только для этого адреса
+        peer: Option<String>,
+    },
+}
+
+#[derive(Subcommand, PartialEq)]
+pub enum HistoryEnum {
+    /// Последние записи
+    List {
+        /// сколько записей показать
+        #[arg(short = 'n', long, default_value_t = 20)]
+        limit: usize,
+        /// вывод в JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Поиск по тексту и адресу
+    Search {
+        query: String,
+        /// вывод в JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Показать запись целиком
+    Show {
+        id: u64,
+        /// вывод в JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Скопировать запись в буфер обмена
+    Copy {
+        #[arg(required_unless_present = "from_menu_line")]
+        id: Option<u64>,
+        /// строка из `history --menu`, без значения — из stdin
+        #[arg(long, value_name = "LINE", num_args = 0..=1, conflicts_with = "id")]
+        from_menu_line: Option<Option<String>>,
+    },
+    /// Удалить всю историю
+    Clear {},
+}
+
+#[derive(Subcommand, PartialEq)]
+pub enum ServiceEnum {
+    /// Записать юнит systemd
+    Install {
+        /// пользовательский юнит в ~/.config/systemd/user
+        #[arg(long)]
+        user: bool,
+        /// ещё и bufsy.socket для socket activation
+        #[arg(long)]
+        socket: bool,
+    },
+}
+
 impl Commands {
     pub async fn run(&self, config_dir: &str, config: Settings) -> Result<()> {
         match &self {
-            Commands::Run { ip, port } => {
-                let ip: String = ip.clone().unwrap_or(config.server.host.to_string());
-                let port = port.unwrap_or(config.server.port);
-                println!("{}:{}", ip, port);
-                let mut ser =
-                    Backend::Axum(ServerAXUM::new(&ip, port, fnthost, config, config_dir));
-                ser.run().await?;
+            Commands::Run {
+                ip,
+                port,
+                interface,
+                sync,
+            } => {
+                daemon::run(
+                    config_dir,
+                    config.clone(),
+                    ip.clone(),
+                    *port,
+                    interface.clone(),
+                    *sync,
+                )
+                .await?;
             }
-            Commands::Send { ip } => {
-                let result =
-                    get_contents(ClipboardType::Regular, Seat::Unspecified, MimeType::Text);
-                match result {
-                    Ok((mut pipe, _)) => {
-                        let mut contents = vec![];
-                        pipe.read_to_end(&mut contents)?;
-                        let text = String::from_utf8_lossy(&contents).to_string();
-                        println!("Pasted: {}", text);
-                        send_message(&text, config.clone(), config_dir, ip.clone()).await?;
-                    }
-
-                    Err(wl_clipboard_rs::paste::Error::NoSeats)
-                    | Err(wl_clipboard_rs::paste::Error::ClipboardEmpty)
-                    | Err(wl_clipboard_rs::paste::Error::NoMimeType) => {
-                        println!(
-                            "The clipboard is empty or doesn't contain text, nothing to worry about."
-                        );
+            Commands::Send {
+                ip,
+                to,
+                json,
+                sensitive,
+            } => {
+                if let Some(text) = clipboard::paste()? {
+                    tracing::trace!(text, "Pasted");
+                    tracing::debug!(bytes = text.len(), "Pasted from clipboard");
+                    let outgoing = Outgoing {
+                        sensitive: *sensitive || clipboard::is_secret(),
+                        text,
+                        ip: ip.clone(),
+                        to: targets(to, &config.default_to.send),
+                    };
+                    let report = send(outgoing, config.clone(), config_dir).await?;
+                    print_report(&report, *json)?;
+                } else {
+                    println!(
+                        "The clipboard is empty or doesn't contain text, nothing to worry about."
+                    );
+                }
+            }
+            Commands::Ping { ip, count } => {
+                let peers: Vec<String> = match ip {
+                    Some(ip) => vec![ip.parse::<Address>()?.to_string()],
+                    None => config
+                        .connections
+                        .iter()
+                        .map(|connection| {
+                            Address::new(&connection.host, connection.port).to_string()
+                        })
+                        .collect(),
+                };
+                if peers.is_empty() {
+                    println!("No peers, use `bufsy ping -i host:port`");
+                }
+                let client = client::build(&config.client)?;
+                let mut failed = 0;
+                for round in 0..*count {
+                    if round > 0 {
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                    }
+                    for peer in &peers {
+                        match client::ping(&client, peer, &config).await {
+                            Ok(latency) => println!("pong from {}: {}ms, key ok", peer, latency),
+                            Err(e) => {
+                                failed += 1;
+                                println!("{}", e);
+                            }
+                        }
                     }
-
-                    Err(err) => Err(err)?,
+                }
+                if failed > 0 {
+                    return Err(anyhow!(
+                        "{} of {} pings failed",
+                        failed,
+                        peers.len() as u32 * count
+                    ));
+                }
+            }
+            Commands::Status { json } => {
+                let daemon = match control::request(config_dir, &Request::Status).await? {
+                    Some(Response::Status(status)) => Some(status),
+                    Some(_) => return Err(anyhow!("Unexpected answer from bufsy run")),
+                    None => None,
+                };
+                let peers: Vec<String> = config
+                    .connections
+                    .iter()
+                    .map(|connection| Address::new(&connection.host, connection.port).to_string())
+                    .collect();
+                let probes =
+                    status::probe_all(&client::build(&config.client)?, &peers, &config).await;
+                print_status(daemon.as_ref(), &probes, *json)?;
+            }
+            Commands::Peer { command } => peer::peer(config_dir, command).await?,
+            Commands::Peers { json } => {
+                let peers = match control::request(config_dir, &Request::Peers).await? {
+                    Some(Response::Peers { peers }) => peers,
+                    Some(_) => return Err(anyhow!("Unexpected answer from bufsy run")),
+                    None => config.connections.clone(),
+                };
+                print_peers(&peers, *json)?;
+            }
+            Commands::History { menu, command } => {
+                let history = History::open(config_dir, &config);
+                if *menu {
+                    for item in history.list()?.iter().rev() {
+                        println!("{}", menu_line(item));
+                    }
+                    return Ok(());
+                }
+                let command = command.as_ref().unwrap_or(&HistoryEnum::List {
+                    limit: 20,
+                    json: false,
+                });
+                match command {
+                    HistoryEnum::List { limit, json } => {
+                        let items = history.list()?;
+                        print_history(&items[items.len().saturating_sub(*limit)..], *json)?;
+                    }
+                    HistoryEnum::Search { query, json } => {
+                        print_history(&history.search(query)?, *json)?;
+                    }
+                    HistoryEnum::Show { id, json } => {
+                        let item = history.get(*id)?;
+                        if *json {
+                            println!("{}", serde_json::to_string_pretty(&item)?);
+                        } else {
+                            println!("{}", item.text);
+                        }
+                    }
+                    HistoryEnum::Copy { id, from_menu_line } => {
+                        let id = match (id, from_menu_line) {
+                            (Some(id), _) => *id,
+                            (None, Some(Some(line))) => menu_id(line)?,
+                            (None, _) => {
+                                let mut line = String::new();
+                                std::io::stdin().read_line(&mut line)?;
+                                menu_id(&line)?
+                            }
+                        };
+                        clipboard::copy(&history.get(id)?.text)?;
+                        println!("Copied item {}", id);
+                    }
+                    HistoryEnum::Clear {} => {
+                        println!("Removed {}", history.clear()?);
+                    }
+                }
+            }
+            Commands::Pull {
+                peer,
+                print,
+                since,
+                json,
+            } => {
+                let peer = peer.parse::<Address>()?.to_string();
+                let client = client::build(&config.client)?;
+                if let Some(since) = since {
+                    let items = pull::history(&client, &peer, &config, *since).await?;
+                    print_history(&items, *json)?;
+                    return Ok(());
+                }
+                let item = pull::latest(&client, &peer, &config).await?;
+                if *print {
+                    println!("{}", item.text);
+                    return Ok(());
+                }
+                clipboard::copy(&item.text)?;
+                println!(
+                    "Copied {} bytes from {}, {} old",
+                    item.text.len(),
+                    peer,
+                    ago(outbox::now().saturating_sub(item.time))
+                );
+                if let Err(e) = History::open(config_dir, &config).record(
+                    Direction::Received,
+                    &peer,
+                    &item.text,
+                    false,
+                ) {
+                    tracing::warn!(error = %e, "Failed to save history");
+                }
+            }
+            Commands::Pick {} => pick::pick(config_dir, &config).await?,
+            Commands::Discover { timeout, add } => {
+                let peers = Discovery::new()?
+                    .browse(Duration::from_secs(*timeout), &fingerprint(&config.key))
+                    .await?;
+                if peers.is_empty() {
+                    println!("No devices with the same key found");
+                }
+                let _lock = lock(config_dir)?;
+                let mut config_mut = load(config_dir)?;
+                let mut changed = false;
+                for peer in peers {
+                    if peer.id.as_deref() == Some(config.device_id.as_str()) {
+                        continue;
+                    }
+                    let known = config_mut.has_connection(&peer.host, peer.port);
+                    println!(
+                        "{} {}{}",
+                        peer.name,
+                        Address::new(&peer.host, peer.port),
+                        if known { " (known)" } else { "" }
+                    );
+                    if *add {
+                        changed |=
+                            config_mut.upsert_connection(peer.id.as_deref(), &peer.host, peer.port);
+                    }
+                }
+                if changed {
+                    save(&config_mut, config_dir)?;
                 }
             }
-            Commands::Echo { text, ip } => {
-                println!("echo {}", text);
-                send_message(text, config.clone(), config_dir, ip.clone()).await?;
+            Commands::Echo {
+                text,
+                ip,
+                to,
+                json,
+                sensitive,
+            } => {
+                tracing::trace!(text, "Echo");
+                let outgoing = Outgoing {
+                    text: text.clone(),
+                    ip: ip.clone(),
+                    to: targets(to, &config.default_to.echo),
+                    sensitive: *sensitive,
+                };
+                let report = send(outgoing, config.clone(), config_dir).await?;
+                print_report(&report, *json)?;
             }
             Commands::Key { command } => match command {
                 KeyEnum::Set { key_update } => {
-                    let mut config_mut = config.clone();
-                    config_mut.key = key_update.to_string();
-                    println!("KEY \"{}\"", config_mut.key);
+                    println!("KEY \"{}\"", key_update);
                     let mut input = String::new();
                     print!("Save key? (y/n): ");
                     std::io::stdout().flush().unwrap();
@@ -116,12 +535,86 @@ impl Commands {
                         || input.trim().to_lowercase() == "Д"
                         || input.trim().to_lowercase() == "да"
                     {
-                        save(&config_mut, &config_dir).expect("Failed to save config");
+                        let _lock = lock(config_dir)?;
+                        let mut config_mut = load(config_dir)?;
+                        config_mut.key = key_update.to_string();
+                        save(&config_mut, config_dir)?;
                         println!("Key saved successfully!");
                     }
                 }
                 KeyEnum::Show {} => println!("KEY \"{}\"", config.key),
             },
+            Commands::Outbox { command } => {
+                let lock = Outbox::lock(config_dir).await?;
+                let mut queue = Outbox::load(config_dir)?;
+                match command {
+                    OutboxEnum::List {} => {
+                        if queue.items.is_empty() {
+                            println!("Outbox is empty");
+                        }
+                        let now = outbox::now();
+                        for item in &queue.items {
+                            println!(
+                                "{} queued {}s ago, attempts {}, next retry in {}s",
+                                item.peer,
+                                now.saturating_sub(item.created),
+                                item.attempts,
+                                item.next_attempt.saturating_sub(now)
+                            );
+                        }
+                    }
+                    OutboxEnum::Flush {} => {
+                        // flush берёт блокировку сам и только на чтение и запись очереди
+                        drop(lock);
+                        let report = Outbox::flush(
+                            config_dir,
+                            &client::build(&config.client)?,
+                            &config.outbox,
+                            true,
+                        )
+                        .await?;
+                        println!(
+                            "Delivered {}, failed {}, expired {}, rejected {}",
+                            report.delivered, report.failed, report.expired, report.rejected
+                        );
+                    }
+                    OutboxEnum::Clear { peer } => {
+                        let before = queue.items.len();
+                        queue
+                            .items
+                            .retain(|item| peer.as_ref().is_some_and(|p| *p != item.peer));
+                        println!("Removed {}", before - queue.items.len());
+                        queue.save(config_dir)?;
+                    }
+                }
+            }
+            Commands::Service { command } => match command {
+                ServiceEnum::Install { user, socket } => {
+                    if !user {
+                        return Err(anyhow::anyhow!(
+                            "Only user units are supported, use `bufsy service install --user`"
+                        ));
+                    }
+                    let dir = dirs::config_dir()
+                        .ok_or_else(|| anyhow::anyhow!("No config directory"))?
+                        .join("systemd/user");
+                    let exe = std::env::current_exe()?;
+                    for path in service::install(
+                        &dir,
+                        &exe.display().to_string(),
+                        config.server.port,
+                        *socket,
+                    )? {
+                        println!("✓ {}", path);
+                    }
+                    println!("systemctl --user daemon-reload");
+                    if *socket {
+                        println!("systemctl --user enable --now bufsy.socket");
+                    } else {
+                        println!("systemctl --user enable --now bufsy.service");
+                    }
+                }
+            },
             Commands::GenerateCompletion { shells } => {
                 let mut cmd = Cli::command();
                 clap_complete::generate(*shells, &mut cmd, "bufsy", &mut std::io::stdout());
@@ -132,73 +625,140 @@ impl Commands {
     }
 }
 
-async fn send_message(
-    message: &str,
+/// Отправка через работающий `bufsy run`, без него — напрямую
+pub(super) async fn send(
+    outgoing: Outgoing,
+    config: Settings,
+    config_dir: &str,
+) -> Result<Vec<Delivery>> {
+    match control::request(config_dir, &Request::Send(outgoing.clone())).await? {
+        Some(Response::Report { deliveries }) => Ok(deliveries),
+        Some(_) => Err(anyhow!("Unexpected answer from bufsy run")),
+        None => send_message(&outgoing, config, config_dir, None).await,
+    }
+}
+
+/// `bufsy config check`: ошибка разбора с местом в файле или список проблем
+pub fn check_config(config_dir: &str) -> Result<()> {
+    let path = format!("{}/config.toml", config_dir);
+    let problems = load(config_dir)?.problems();
+    if problems.is_empty() {
+        println!("{}: ok", path);
+        return Ok(());
+    }
+    for problem in &problems {
+        println!("{}: {}", path, problem);
+    }
+    Err(anyhow!(
+        "Config check failed: {} problem(s)",
+        problems.len()
+    ))
+}
+
+/// `--to`, а без него — получатели команды по умолчанию
+fn targets(to: &[String], default: &[String]) -> Vec<String> {
+    if to.is_empty() { default } else { to }.to_vec()
+}
+
+/// `session` есть, когда отправляет сам демон: конверт попадает в его кэш повторов и метрики
+#[tracing::instrument(skip_all, fields(bytes = outgoing.text.len()))]
+pub(super) async fn send_message(
+    outgoing: &Outgoing,
     config: Settings,
     config_dir: &str,
-    address: Option<String>,
-) -> Result<()> {
-    let client = reqwest::Client::new();
-
-    let nonce = hex::encode(generate_nonce());
-    let test_encrypted = format!(
-        "{}|{}|{}",
-        encrypt(&message, &config.key, &nonce).unwrap(),
-        nonce,
-        config.server.port
-    );
-    let mut resp: Option<Result<reqwest::Response, reqwest::Error>> = None;
-    if let Some(address) = address.clone() {
-        resp = Some(
-            client
-                .post(format!("http://{}/text", address))
-                .body(test_encrypted.clone())
-                .send()
-                .await,
+    session: Option<&Session>,
+) -> Result<Vec<Delivery>> {
+    let client = client::build(&config.client)?;
+
+    let envelope = Envelope {
+        sensitive: outgoing.sensitive,
+        // демон помечает свои записи для `--sync` на других устройствах
+        origin: session.map(|_| config.device_id.clone()),
+        clock: session.map(|session| session.sync.stamp(&config.device_id, &outgoing.text)),
+        hops: Some(config.gossip.hops).filter(|&hops| config.gossip.enabled && hops > 0),
+        ..Envelope::new(config.server.port, &config.device_id)
+    }
+    .seal(&outgoing.text, &config.key)?;
+    if let Some(session) = session {
+        // копия, пересланная обратно другими устройствами, будет повтором
+        session.remember(envelope.id()).await;
+    }
+    let envelope = envelope.to_string();
+    let mut peers = config.recipients(&outgoing.to)?;
+    let address = outgoing
+        .ip
+        .clone()
+        .map(|address| address.parse::<Address>().map(|a| a.to_string()))
+        .transpose()?;
+    if let Some(address) = &address
+        && !peers.contains(address)
+    {
+        peers.push(address.clone());
+    }
+
+    let mut report = client::fan_out(&client, &peers, &envelope).await;
+    for delivery in &report {
+        tracing::debug!(
+            peer = delivery.peer,
+            status = ?delivery.status,
+            latency_ms = delivery.latency_ms,
+            error = delivery.error,
+            "Delivery"
         );
     }
-    for connection in config.connections {
-        println!("http://{}:{}/text", connection.host, connection.port);
-        let resp = client
-            .post(format!(
-                "http://{}:{}/text",
-                connection.host, connection.port
-            ))
-            .body(test_encrypted.clone())
-            .send()
-            .await;
-        if let Err(_e) = resp {
-            println!(
-                "Failed to send message to {}!",
-                format!("{}:{}", connection.host, connection.port)
-            );
+    if let Some(session) = session {
+        for delivery in &report {
+            if delivery.status.is_delivered() {
+                Metrics::inc(&session.metrics.bytes_out, envelope.len() as u64);
+            } else {
+                session.metrics.send_failed(&config, &delivery.peer);
+            }
         }
     }
-    if let Some(resp) = resp {
-        if let Err(e) = resp {
-            println!("Failed to send message to server! '{}'", e);
-        } else if let Ok(resp) = resp {
-            if !resp.status().is_success() && resp.text().await.unwrap() != "oK" {
-                return Ok(());
-            }
-            let address = address.clone().unwrap();
-            let address_s: Vec<&str> = address.split(":").collect();
-            if address_s.len() != 2 {
-                return Err(anyhow::anyhow!("Invalid address format {}", address));
-            }
-            let mut config_mut = load(&config_dir);
-            config_mut.new_connection(address_s[0], address_s[1].parse::<u16>()?);
-            if load(&config_dir).connections.contains(&Server {
-                host: address_s[0].to_string(),
-                port: address_s[1].parse::<u16>()?,
-            }) {
-                return Ok(());
-            }
-            println!("Connection added! {} {:?}", config_dir, config_mut);
-            save(&config_mut, &config_dir)?;
+
+    if report.iter().any(|d| d.status.is_retryable()) {
+        let _lock = Outbox::lock(config_dir).await?;
+        let mut queue = Outbox::load(config_dir)?;
+        for delivery in report.iter_mut().filter(|d| d.status.is_retryable()) {
+            queue.push(&delivery.peer, &envelope, outbox::now());
+            delivery.queued = true;
+        }
+        queue.save(config_dir)?;
+    }
+
+    // в истории только те, кому текст дошёл или ждёт в outbox
+    let reached: Vec<&str> = report
+        .iter()
+        .filter(|d| d.status.is_delivered() || d.queued)
+        .map(|d| d.peer.as_str())
+        .collect();
+    if !reached.is_empty()
+        && let Err(e) = History::open(config_dir, &config).record(
+            Direction::Sent,
+            &reached.join(", "),
+            &outgoing.text,
+            outgoing.sensitive,
+        )
+    {
+        tracing::warn!(error = %e, "Failed to save history");
+    }
+
+    let Some(address) = address else {
+        return Ok(report);
+    };
+    if report
+        .iter()
+        .any(|d| d.peer == address && d.status.is_delivered())
+    {
+        let parsed: Address = address.parse()?;
+        let _lock = lock(config_dir)?;
+        let mut config_mut = load(config_dir)?;
+        if config_mut.upsert_connection(None, &parsed.host, parsed.port) {
+            tracing::info!(peer = address, "Connection added");
+            save(&config_mut, config_dir)?;
         }
     }
-    Ok(())
+    Ok(report)
 }
 
 #[cfg(test)]
@@ -212,8 +772,17 @@ mod tests {
         let config = test_load_config();
         let config_dir = test_config_dir();
 
-        send_message("text :>", config.clone(), &config_dir, None)
+        let outgoing = Outgoing {
+            text: "text :>".to_string(),
+            ..Default::default()
+        };
+        std::fs::create_dir_all(&config_dir).unwrap();
+        let history = History::open(&config_dir, &config);
+        let before = history.list().unwrap().len();
+        send_message(&outgoing, config.clone(), &config_dir, None)
             .await
             .unwrap();
+        // отправлять было некому, в истории ничего не появилось
+        assert_eq!(history.list().unwrap().len(), before);
     }
 }
@@ -0,0 +1,335 @@
+use super::commands::send_message;
+use crate::{
+    app::{
+        announce, clipboard,
+        encryption::fingerprint,
+        outbox::{self, Outbox, now},
+        receive,
+        session::Session,
+        sync,
+    },
+    config::{Settings, load},
+    transport::{
+        Backend,
+        address::{Address, local_addresses},
+        axum::ServerAXUM,
+        control::{self, DaemonStatus, Outgoing, Request, Response},
+        mdns::Discovery,
+        systemd,
+    },
+};
+use anyhow::Result;
+use sd_notify::NotifyState;
+use std::{sync::Arc, time::Duration};
+use tokio::{
+    signal::unix::{SignalKind, signal},
+    sync::{RwLock, watch},
+};
+
+/// Как часто `--sync` проверяет буфер обмена
+const CLIPBOARD_POLL: Duration = Duration::from_millis(500);
+
+/// `bufsy run`: сервер приёма и фоновые задачи до SIGINT/SIGTERM
+pub async fn run(
+    config_dir: &str,
+    mut config: Settings,
+    ip: Option<String>,
+    port: Option<u16>,
+    interface: Option<String>,
+    sync: bool,
+) -> Result<()> {
+    if interface.is_some() {
+        config.network.interface = interface.clone();
+    }
+    let mut ip: String = ip.unwrap_or(config.server.host.to_string());
+    let port = port.unwrap_or(config.server.port);
+    if let Ok(addr) = ip.parse::<std::net::IpAddr>()
+        && !addr.is_unspecified()
+        && !addr.is_loopback()
+        && !local_addresses(None).contains(&addr)
+    {
+        // адрес из старого конфига, сеть с тех пор сменилась
+        tracing::warn!(%addr, "Address is not on this machine, listening on all interfaces");
+        ip = "::".to_string();
+    }
+
+    let control_listener = control::bind(config_dir)?;
+    let (stop, shutdown) = watch::channel(false);
+    let session = Arc::new(Session::new());
+    let handler = Arc::new(receive::handlers(config_dir, sync.then(|| session.clone())));
+    let mut server = ServerAXUM::new(&ip, port, handler, config.clone())
+        .with_session(session.clone())
+        .with_shutdown(shutdown);
+    if config.metrics.enabled {
+        server = server.with_metrics();
+    }
+    if config.history.enabled {
+        server = server.with_history(config_dir);
+    }
+    let listen = match systemd::activated_listener()? {
+        Some(listener) => {
+            let listen = listener.local_addr()?.to_string();
+            tracing::info!(%listen, "Listening on a socket from systemd");
+            server = server.with_listener(listener);
+            listen
+        }
+        None => {
+            let listen = Address::new(&ip, port).to_string();
+            tracing::info!(%listen, "Listening");
+            listen
+        }
+    };
+
+    let discovery = Discovery::new().and_then(|d| {
+        d.advertise(port, &fingerprint(&config.key), &config.device_id)
+            .map(|_| d)
+    });
+    if let Err(e) = &discovery {
+        tracing::warn!(error = %e, "mDNS announce failed");
+    }
+    let mut tasks = vec![
+        tokio::spawn(outbox::retry_loop(
+            config_dir.to_string(),
+            server.settings(),
+        )),
+        tokio::spawn(announce::watch_loop(
+            config_dir.to_string(),
+            server.settings(),
+            port,
+            interface,
+        )),
+        tokio::spawn(systemd::watchdog_loop()),
+        tokio::spawn(signals(stop, server.settings(), config_dir.to_string())),
+        tokio::spawn(control::serve(control_listener, {
+            let control = Control {
+                settings: server.settings(),
+                session: server.session(),
+                config_dir: config_dir.to_string(),
+                listen,
+            };
+            move |request| control.clone().handle(request)
+        })),
+    ];
+    if sync {
+        tracing::info!("Syncing the clipboard with all devices");
+        tasks.push(tokio::spawn(clipboard_loop(
+            server.settings(),
+            session,
+            config_dir.to_string(),
+        )));
+    }
+
+    systemd::notify(NotifyState::Ready);
+    let result = Backend::Axum(server).run().await;
+
+    // outbox записывается после каждого прохода flush: если остановить его посередине,
+    // уже доставленное уйдёт ещё раз при следующем запуске, и получатель отбросит повтор по id
+    for task in tasks {
+        task.abort();
+    }
+    drop(discovery);
+    let _ = std::fs::remove_file(control::socket_path(config_dir));
+    tracing::info!("Bufsy stopped");
+    result
+}
+
+/// `--sync`: рассылает текст, который скопировали на этом устройстве. Принятая запись
+/// уже текущая в `session.sync`, поэтому её появление в буфере обмена не уходит обратно.
+async fn clipboard_loop(
+    settings: Arc<RwLock<Settings>>,
+    session: Arc<Session>,
+    config_dir: String,
+) {
+    let paste = || async {
+        match tokio::task::spawn_blocking(clipboard::paste).await {
+            Ok(Ok(text)) => text,
+            Ok(Err(e)) => {
+                tracing::debug!(error = %e, "Failed to read the clipboard");
+                None
+            }
+            Err(_) => None,
+        }
+    };
+    // то, что было в буфере до запуска, не рассылаем
+    let mut last = paste().await.map(|text| sync::hash(&text));
+    let mut tick = tokio::time::interval(CLIPBOARD_POLL);
+    loop {
+        tick.tick().await;
+        let Some(text) = paste().await else {
+            continue;
+        };
+        let hash = sync::hash(&text);
+        if last == Some(hash) || text.is_empty() {
+            continue;
+        }
+        last = Some(hash);
+        if session.sync.is_current(&text) {
+            continue;
+        }
+        if tokio::task::spawn_blocking(clipboard::is_secret)
+            .await
+            .unwrap_or(true)
+        {
+            tracing::debug!("Password manager secret, not synced");
+            continue;
+        }
+        let config = settings.read().await.clone();
+        let outgoing = Outgoing {
+            text,
+            ..Default::default()
+        };
+        match send_message(&outgoing, config, &config_dir, Some(&session)).await {
+            Ok(report) => tracing::info!(
+                delivered = report.iter().filter(|d| d.status.is_delivered()).count(),
+                peers = report.len(),
+                "Synced"
+            ),
+            Err(e) => tracing::warn!(error = %e, "Sync failed"),
+        }
+    }
+}
+
+/// SIGINT/SIGTERM — плавная остановка, SIGHUP — перечитать конфиг
+async fn signals(stop: watch::Sender<bool>, settings: Arc<RwLock<Settings>>, config_dir: String) {
+    let (Ok(mut term), Ok(mut hup)) = (
+        signal(SignalKind::terminate()),
+        signal(SignalKind::hangup()),
+    ) else {
+        tracing::error!("Failed to install signal handlers");
+        return;
+    };
+    loop {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => break,
+            _ = term.recv() => break,
+            _ = hup.recv() => reload(&settings, &config_dir).await,
+        }
+    }
+
+    tracing::info!("Shutting down, waiting for requests in flight (Ctrl+C again to force)");
+    systemd::notify(NotifyState::Stopping);
+    let _ = stop.send(true);
+
+    let _ = tokio::signal::ctrl_c().await;
+    std::process::exit(130);
+}
+
+/// Запросы `bufsy send/status/peers` через управляющий сокет
+#[derive(Clone)]
+struct Control {
+    settings: Arc<RwLock<Settings>>,
+    session: Arc<Session>,
+    config_dir: String,
+    listen: String,
+}
+
+impl Control {
+    async fn handle(self, request: Request) -> Response {
+        self.respond(request)
+            .await
+            .unwrap_or_else(|e| Response::Error {
+                message: e.to_string(),
+            })
+    }
+
+    async fn respond(&self, request: Request) -> Result<Response> {
+        let config = self.settings.read().await.clone();
+        Ok(match request {
+            Request::Send(outgoing) => Response::Report {
+                deliveries: send_message(&outgoing, config, &self.config_dir, Some(&self.session))
+                    .await?,
+            },
+            Request::Status => Response::Status(DaemonStatus {
+                pid: std::process::id(),
+                version: env!("CARGO_PKG_VERSION").to_string(),
+                device_id: config.device_id,
+                listen: self.listen.clone(),
+                uptime: now().saturating_sub(self.session.started),
+                peers: config.connections.len(),
+                outbox: Outbox::load(&self.config_dir)?.items.len(),
+                seen: self.session.seen().await,
+            }),
+            Request::Peers => Response::Peers {
+                peers: config.connections,
+            },
+            Request::Reload => {
+                reload(&self.settings, &self.config_dir).await;
+                Response::Reloaded
+            }
+        })
+    }
+}
+
+/// Перечитывает конфиг в общие настройки: их читают обработчики, outbox, слежение за сетью
+/// и `--sync`. Адрес сервера, лимиты, метрики, история и логи настраиваются при запуске.
+async fn reload(settings: &RwLock<Settings>, config_dir: &str) {
+    systemd::notify(NotifyState::Reloading);
+    let dir = config_dir.to_string();
+    match tokio::task::spawn_blocking(move || load(&dir)).await {
+        Ok(Ok(config)) => {
+            let mut settings = settings.write().await;
+            let restart = needs_restart(&settings, &config);
+            if !restart.is_empty() {
+                tracing::warn!(?restart, "Changes in these sections apply after a restart");
+            }
+            *settings = config;
+            tracing::info!("Config reloaded");
+        }
+        Ok(Err(e)) => tracing::error!(error = %e, "Failed to reload config, keeping the old one"),
+        Err(e) => tracing::error!(error = %e, "Failed to reload config, keeping the old one"),
+    }
+    systemd::notify(NotifyState::Ready);
+}
+
+/// Секции, которые `bufsy run` читает только при запуске
+fn needs_restart(old: &Settings, new: &Settings) -> Vec<&'static str> {
+    [
+        ("server", old.server != new.server),
+        ("limits", old.limits != new.limits),
+        ("metrics", old.metrics != new.metrics),
+        (
+            "history.enabled",
+            old.history.enabled != new.history.enabled,
+        ),
+        ("log", old.log != new.log),
+    ]
+    .into_iter()
+    .filter_map(|(section, changed)| changed.then_some(section))
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{load_config::tests::test_load_config, save};
+
+    #[tokio::test]
+    async fn reload_config() {
+        let config_dir = "test_dir/reload";
+        std::fs::create_dir_all(config_dir).unwrap();
+        let mut config = test_load_config();
+        save(&config, config_dir).unwrap();
+        let settings = RwLock::new(config.clone());
+
+        config.key = "0".repeat(64);
+        save(&config, config_dir).unwrap();
+        reload(&settings, config_dir).await;
+        assert_eq!(settings.read().await.key, config.key);
+
+        std::fs::write(format!("{}/config.toml", config_dir), "key = ").unwrap();
+        reload(&settings, config_dir).await;
+        assert_eq!(settings.read().await.key, config.key);
+    }
+
+    #[test]
+    fn restart_sections() {
+        let old = test_load_config();
+        let mut new = old.clone();
+        new.key = "0".repeat(64);
+        new.outbox.retry_min += 1;
+        assert!(needs_restart(&old, &new).is_empty());
+        new.limits.rate += 1;
+        new.server.port += 1;
+        assert_eq!(needs_restart(&old, &new), ["server", "limits"]);
+    }
+}
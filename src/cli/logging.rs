@@ -0,0 +1,77 @@
+use anyhow::Result;
+use clap::ValueEnum;
+use std::{
+    fs::{OpenOptions, Permissions},
+    os::unix::fs::{OpenOptionsExt, PermissionsExt},
+    sync::Mutex,
+};
+use tracing_subscriber::{
+    EnvFilter, Layer, Registry, filter::LevelFilter, fmt, layer::SubscriberExt,
+    util::SubscriberInitExt,
+};
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+/// `-v`/`-q` в уровень журнала, по умолчанию info.
+/// Содержимое буфера обмена пишется только на trace (`-vv`).
+pub fn level(verbose: u8, quiet: u8) -> LevelFilter {
+    match verbose as i16 - quiet as i16 {
+        ..=-2 => LevelFilter::ERROR,
+        -1 => LevelFilter::WARN,
+        0 => LevelFilter::INFO,
+        1 => LevelFilter::DEBUG,
+        _ => LevelFilter::TRACE,
+    }
+}
+
+/// Журнал в stderr (stdout остаётся для вывода команд) и, если задан `file`, ещё и в файл.
+/// `RUST_LOG` перекрывает `-v`/`-q`.
+pub fn init(level: LevelFilter, format: LogFormat, file: Option<&str>) -> Result<()> {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| {
+        EnvFilter::new(format!("{},bufsy={}", level.min(LevelFilter::WARN), level))
+    });
+
+    let mut layers: Vec<Box<dyn Layer<Registry> + Send + Sync>> = vec![match format {
+        LogFormat::Text => fmt::layer().with_writer(std::io::stderr).boxed(),
+        LogFormat::Json => fmt::layer().json().with_writer(std::io::stderr).boxed(),
+    }];
+    if let Some(path) = file {
+        // с `-vv` в журнал попадает содержимое буфера обмена: читать его может только владелец,
+        // права журнала от старых версий тоже поджимаются
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .mode(0o600)
+            .open(path)?;
+        file.set_permissions(Permissions::from_mode(0o600))?;
+        let file = Mutex::new(file);
+        layers.push(match format {
+            LogFormat::Text => fmt::layer().with_ansi(false).with_writer(file).boxed(),
+            LogFormat::Json => fmt::layer().json().with_writer(file).boxed(),
+        });
+    }
+
+    tracing_subscriber::registry()
+        .with(layers)
+        .with(filter)
+        .try_init()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verbosity_levels() {
+        assert_eq!(level(0, 0), LevelFilter::INFO);
+        assert_eq!(level(2, 0), LevelFilter::TRACE);
+        assert_eq!(level(5, 0), LevelFilter::TRACE);
+        assert_eq!(level(0, 1), LevelFilter::WARN);
+        assert_eq!(level(0, 3), LevelFilter::ERROR);
+    }
+}
@@ -1,6 +1,12 @@
 pub mod args;
 pub mod commands;
+mod daemon;
 mod init;
+mod logging;
+mod peer;
+mod pick;
+mod report;
+mod service;
 
 pub use args::Cli;
 pub use init::init;
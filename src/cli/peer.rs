@@ -0,0 +1,210 @@
+use super::{
+    commands::PeerEnum,
+    report::{print_peers, print_probes},
+};
+use crate::{
+    config::{Settings, load, lock, save},
+    transport::{
+        address::Address,
+        client,
+        control::{self, Request},
+        status,
+    },
+};
+use anyhow::{Result, anyhow};
+
+/// `bufsy peer`: правка `connections` без ручного редактирования конфига
+pub async fn peer(config_dir: &str, command: &PeerEnum) -> Result<()> {
+    let config = load(config_dir)?;
+    match command {
+        PeerEnum::List { json } => return print_peers(&config.connections, *json),
+        PeerEnum::Test { peer, json } => {
+            let peers = match peer {
+                Some(peer) => vec![config.connections[config.find_connection(peer)?].clone()],
+                None => config.connections.clone(),
+            };
+            let peers: Vec<String> = peers
+                .iter()
+                .map(|c| Address::new(&c.host, c.port).to_string())
+                .collect();
+            let probes = status::probe_all(&client::build(&config.client)?, &peers, &config).await;
+            print_probes(&probes, *json)?;
+            let failed = probes.iter().filter(|p| p.key_match != Some(true)).count();
+            if failed > 0 {
+                return Err(anyhow!("{} of {} peers failed", failed, probes.len()));
+            }
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    {
+        // пока шли проверки, демон мог дописать новый адрес
+        let _lock = lock(config_dir)?;
+        let mut config = load(config_dir)?;
+        println!("{}", edit(&mut config, command)?);
+        save(&config, config_dir)?;
+    }
+    // работающий `bufsy run` отправляет по своей копии настроек
+    control::request(config_dir, &Request::Reload).await?;
+    Ok(())
+}
+
+/// Меняет `connections` и возвращает, что сделано
+fn edit(config: &mut Settings, command: &PeerEnum) -> Result<String> {
+    Ok(match command {
+        PeerEnum::Add { address, name } => {
+            let address: Address = address.parse()?;
+            if let Ok(index) = config.find_connection(&address.to_string()) {
+                return Err(anyhow!(
+                    "{} is already a peer{}",
+                    address,
+                    config.connections[index]
+                        .name
+                        .as_ref()
+                        .map(|name| format!(" ({})", name))
+                        .unwrap_or_default()
+                ));
+            }
+            if let Some(name) = name {
+                config.check_name(name, None)?;
+            }
+            config.new_connection(&address.host, address.port).name = name.clone();
+            format!("Added {}", address)
+        }
+        PeerEnum::Remove { peer } => {
+            let index = config.find_connection(peer)?;
+            let removed = config.connections.remove(index);
+            // группы и `default_to` не должны слать на удалённый адрес
+            let places = config.rewrite_targets(|target| removed.is(target), None);
+            format!(
+                "Removed {}{}",
+                Address::new(&removed.host, removed.port),
+                updated(" and dropped it from", &places)
+            )
+        }
+        PeerEnum::Rename { peer, name } => {
+            let index = config.find_connection(peer)?;
+            config.check_name(name, Some(index))?;
+            let old = config.connections[index].name.replace(name.clone());
+            let places = match &old {
+                Some(old) => config.rewrite_targets(|target| target == old, Some(name)),
+                None => Vec::new(),
+            };
+            let connection = &config.connections[index];
+            format!(
+                "{} is now {}{}",
+                Address::new(&connection.host, connection.port),
+                name,
+                updated(", updated", &places)
+            )
+        }
+        PeerEnum::Enable { peer } | PeerEnum::Disable { peer } => {
+            let index = config.find_connection(peer)?;
+            let connection = &mut config.connections[index];
+            connection.disabled = matches!(command, PeerEnum::Disable { .. });
+            format!(
+                "{} {}",
+                Address::new(&connection.host, connection.port),
+                if connection.disabled {
+                    "disabled"
+                } else {
+                    "enabled"
+                }
+            )
+        }
+        PeerEnum::List { .. } | PeerEnum::Test { .. } => unreachable!(),
+    })
+}
+
+/// Хвост сообщения про группы и `default_to`, которые пришлось поправить
+fn updated(prefix: &str, places: &[String]) -> String {
+    if places.is_empty() {
+        return String::new();
+    }
+    format!("{} {}", prefix, places.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::load_config::tests::test_load_config;
+
+    #[test]
+    fn edit_peers() {
+        let mut config = test_load_config();
+        let add = |address: &str, name: Option<&str>| PeerEnum::Add {
+            address: address.to_string(),
+            name: name.map(str::to_string),
+        };
+        edit(&mut config, &add("192.168.1.5:9296", Some("laptop"))).unwrap();
+        edit(&mut config, &add("[::1]:9296", None)).unwrap();
+        assert!(edit(&mut config, &add("192.168.1.5:9296", None)).is_err());
+        assert!(edit(&mut config, &add("10.0.0.2:9296", Some("laptop"))).is_err());
+        assert!(edit(&mut config, &add("10.0.0.2", None)).is_err());
+
+        let peer = "[::1]:9296".to_string();
+        edit(
+            &mut config,
+            &PeerEnum::Rename {
+                peer: peer.clone(),
+                name: "phone".to_string(),
+            },
+        )
+        .unwrap();
+        edit(
+            &mut config,
+            &PeerEnum::Disable {
+                peer: "phone".to_string(),
+            },
+        )
+        .unwrap();
+        assert!(config.connections[1].disabled);
+        assert_eq!(config.recipients(&[]).unwrap(), ["192.168.1.5:9296"]);
+
+        edit(
+            &mut config,
+            &PeerEnum::Remove {
+                peer: "laptop".to_string(),
+            },
+        )
+        .unwrap();
+        assert_eq!(config.connections.len(), 1);
+        assert_eq!(config.connections[0].name.as_deref(), Some("phone"));
+    }
+
+    #[test]
+    fn edit_keeps_targets() {
+        let mut config = test_load_config();
+        config.new_connection("192.168.1.5", 9296).name = Some("laptop".to_string());
+        config.new_connection("192.168.1.6", 9296).name = Some("phone".to_string());
+        config.groups.insert(
+            "home".to_string(),
+            vec!["laptop".to_string(), "192.168.1.6:9296".to_string()],
+        );
+        config.default_to.send = vec!["laptop".to_string()];
+
+        let message = edit(
+            &mut config,
+            &PeerEnum::Rename {
+                peer: "laptop".to_string(),
+                name: "work".to_string(),
+            },
+        )
+        .unwrap();
+        assert!(message.ends_with("updated groups.home, default_to.send"));
+        assert_eq!(config.groups["home"], ["work", "192.168.1.6:9296"]);
+        assert_eq!(config.default_to.send, ["work"]);
+
+        // адрес удалённого устройства не остаётся в группе
+        edit(
+            &mut config,
+            &PeerEnum::Remove {
+                peer: "phone".to_string(),
+            },
+        )
+        .unwrap();
+        assert_eq!(config.groups["home"], ["work"]);
+        assert!(config.problems().is_empty());
+    }
+}
@@ -0,0 +1,388 @@
+use super::{
+    commands::send,
+    report::{ago, preview},
+};
+use crate::{
+    app::{
+        clipboard,
+        history::{Direction, History, Item},
+        outbox::now,
+    },
+    config::Settings,
+    transport::{address::Address, control::Outgoing},
+};
+use anyhow::Result;
+use fuzzy_matcher::{FuzzyMatcher, skim::SkimMatcherV2};
+use ratatui::{
+    DefaultTerminal, Frame,
+    crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout, Rect},
+    style::{Style, Stylize},
+    text::{Line, Text},
+    widgets::{Block, Clear, List, ListItem, ListState, Paragraph, Wrap},
+};
+
+/// `bufsy pick`: история с нечётким поиском; Enter копирует, Tab отправляет на устройство
+pub async fn pick(config_dir: &str, config: &Settings) -> Result<()> {
+    let mut items = History::open(config_dir, config).list()?;
+    if items.is_empty() {
+        println!("History is empty");
+        return Ok(());
+    }
+    items.reverse();
+    let peers = config
+        .connections
+        .iter()
+        .map(|connection| {
+            connection
+                .name
+                .clone()
+                .unwrap_or_else(|| Address::new(&connection.host, connection.port).to_string())
+        })
+        .collect();
+
+    let mut picker = Picker::new(items, peers);
+    let mut terminal = ratatui::try_init()?;
+    let result = picker.run(&mut terminal, config_dir, config).await;
+    ratatui::restore();
+    if let Some(message) = result? {
+        println!("{}", message);
+    }
+    Ok(())
+}
+
+#[derive(Debug, PartialEq)]
+enum Action {
+    None,
+    Quit,
+    /// индекс в `items`
+    Copy(usize),
+    /// `None` — всем устройствам
+    Send(usize, Option<String>),
+}
+
+struct Picker {
+    /// от новых к старым
+    items: Vec<Item>,
+    query: String,
+    /// индексы `items`, подходящие под `query`, лучшие первыми
+    matches: Vec<usize>,
+    list: ListState,
+    peers: Vec<String>,
+    /// открыт выбор устройства: 0 — все, дальше `peers`
+    target: Option<ListState>,
+    status: String,
+}
+
+impl Picker {
+    fn new(items: Vec<Item>, peers: Vec<String>) -> Self {
+        let mut picker = Self {
+            items,
+            query: String::new(),
+            matches: Vec::new(),
+            list: ListState::default(),
+            peers,
+            target: None,
+            status: String::new(),
+        };
+        picker.filter();
+        picker
+    }
+
+    /// Пустой запрос — вся история по порядку, иначе по убыванию совпадения
+    fn filter(&mut self) {
+        let matcher = SkimMatcherV2::default();
+        let mut scored: Vec<(i64, usize)> = self
+            .items
+            .iter()
+            .enumerate()
+            .filter_map(|(index, item)| {
+                if self.query.is_empty() {
+                    return Some((0, index));
+                }
+                let text = if item.sensitive { "" } else { &item.text };
+                let score = [text, item.peer.as_str()]
+                    .iter()
+                    .filter_map(|choice| matcher.fuzzy_match(choice, &self.query))
+                    .max()?;
+                Some((score, index))
+            })
+            .collect();
+        scored.sort_by_key(|(score, index)| (-score, *index));
+        self.matches = scored.into_iter().map(|(_, index)| index).collect();
+        self.list.select((!self.matches.is_empty()).then_some(0));
+    }
+
+    fn selected(&self) -> Option<usize> {
+        self.list
+            .selected()
+            .and_then(|row| self.matches.get(row).copied())
+    }
+
+    fn step(&mut self, down: bool) {
+        let Some(row) = self.list.selected() else {
+            return;
+        };
+        let row = if down {
+            (row + 1).min(self.matches.len() - 1)
+        } else {
+            row.saturating_sub(1)
+        };
+        self.list.select(Some(row));
+    }
+
+    fn key(&mut self, key: KeyEvent) -> Action {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        if let Some(target) = &mut self.target {
+            match key.code {
+                KeyCode::Esc => self.target = None,
+                KeyCode::Up => target.select_previous(),
+                KeyCode::Down if target.selected() < Some(self.peers.len()) => target.select_next(),
+                KeyCode::Enter => {
+                    let row = target.selected().unwrap_or_default();
+                    self.target = None;
+                    if let Some(index) = self.selected() {
+                        let peer = row.checked_sub(1).map(|i| self.peers[i].clone());
+                        return Action::Send(index, peer);
+                    }
+                }
+                _ => {}
+            }
+            return Action::None;
+        }
+
+        match key.code {
+            KeyCode::Esc => return Action::Quit,
+            KeyCode::Char('c') if ctrl => return Action::Quit,
+            KeyCode::Enter => {
+                if let Some(index) = self.selected() {
+                    return Action::Copy(index);
+                }
+            }
+            KeyCode::Tab if self.selected().is_some() => {
+                self.target = Some(ListState::default().with_selected(Some(0)));
+            }
+            KeyCode::Up => self.step(false),
+            KeyCode::Char('p') if ctrl => self.step(false),
+            KeyCode::Down => self.step(true),
+            KeyCode::Char('n') if ctrl => self.step(true),
+            KeyCode::Backspace => {
+                self.query.pop();
+                self.filter();
+            }
+            KeyCode::Char(c) if !ctrl => {
+                self.query.push(c);
+                self.filter();
+            }
+            _ => {}
+        }
+        Action::None
+    }
+
+    /// Возвращает сообщение, которое печатается после выхода из интерфейса
+    async fn run(
+        &mut self,
+        terminal: &mut DefaultTerminal,
+        config_dir: &str,
+        config: &Settings,
+    ) -> Result<Option<String>> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            match self.key(key) {
+                Action::None => {}
+                Action::Quit => return Ok(None),
+                Action::Copy(index) => {
+                    let item = &self.items[index];
+                    clipboard::copy(&item.text)?;
+                    return Ok(Some(format!("Copied item {}", item.id)));
+                }
+                Action::Send(index, peer) => {
+                    let item = &self.items[index];
+                    let outgoing = Outgoing {
+                        text: item.text.clone(),
+                        to: peer.into_iter().collect(),
+                        sensitive: item.sensitive,
+                        ..Default::default()
+                    };
+                    self.status = match send(outgoing, config.clone(), config_dir).await {
+                        Ok(report) => format!(
+                            "Item {}: delivered to {} of {}, queued {}",
+                            item.id,
+                            report.iter().filter(|d| d.status.is_delivered()).count(),
+                            report.len(),
+                            report.iter().filter(|d| d.queued).count()
+                        ),
+                        Err(e) => format!("Item {}: {}", item.id, e),
+                    };
+                }
+            }
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [input, body, help] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Min(1),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [list, preview_area] =
+            Layout::horizontal([Constraint::Percentage(40), Constraint::Percentage(60)])
+                .areas(body);
+
+        frame.render_widget(Line::from(format!("> {}", self.query)), input);
+        frame.set_cursor_position((input.x + 2 + self.query.chars().count() as u16, input.y));
+
+        let now = now();
+        let rows: Vec<ListItem> = self
+            .matches
+            .iter()
+            .map(|&index| {
+                let item = &self.items[index];
+                ListItem::new(format!(
+                    "{:>4} {}",
+                    ago(now.saturating_sub(item.time)),
+                    preview(item)
+                ))
+            })
+            .collect();
+        let title = format!(" {}/{} ", self.matches.len(), self.items.len());
+        frame.render_stateful_widget(
+            List::new(rows)
+                .block(Block::bordered().title(title))
+                .highlight_style(Style::new().reversed()),
+            list,
+            &mut self.list,
+        );
+
+        let details = match self.selected().map(|index| &self.items[index]) {
+            Some(item) => details(item, now),
+            None => Text::from("No matches"),
+        };
+        frame.render_widget(
+            Paragraph::new(details)
+                .block(Block::bordered())
+                .wrap(Wrap { trim: false }),
+            preview_area,
+        );
+
+        let hint = if self.status.is_empty() {
+            "Enter copy · Tab send to a device · Esc quit"
+        } else {
+            &self.status
+        };
+        frame.render_widget(Line::from(hint).dim(), help);
+
+        if let Some(target) = &mut self.target {
+            let rows: Vec<ListItem> = std::iter::once("all devices".to_string())
+                .chain(self.peers.iter().cloned())
+                .map(ListItem::new)
+                .collect();
+            let area = centered(body, 50, rows.len() as u16 + 2);
+            frame.render_widget(Clear, area);
+            frame.render_stateful_widget(
+                List::new(rows)
+                    .block(Block::bordered().title(" Send to "))
+                    .highlight_style(Style::new().reversed()),
+                area,
+                target,
+            );
+        }
+    }
+}
+
+/// Свойства записи и текст целиком; текст секрета не показывается
+fn details(item: &Item, now: u64) -> Text<'static> {
+    let direction = match item.direction {
+        Direction::Sent => "sent to",
+        Direction::Received => "received from",
+    };
+    let mut text = Text::from(vec![
+        Line::from(format!("#{} {} {}", item.id, direction, item.peer)).bold(),
+        Line::from(format!(
+            "{} ago · {} · {} bytes · {} lines",
+            ago(now.saturating_sub(item.time)),
+            item.mime,
+            item.text.len(),
+            item.text.lines().count()
+        ))
+        .dim(),
+        Line::default(),
+    ]);
+    if item.sensitive {
+        text.push_line("(sensitive)");
+    } else {
+        text.extend(Text::raw(item.text.clone()));
+    }
+    text
+}
+
+/// Прямоугольник `percent`% ширины и `height` строк посередине `area`
+fn centered(area: Rect, percent: u16, height: u16) -> Rect {
+    let width = area.width * percent / 100;
+    let height = height.min(area.height);
+    Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + (area.height - height) / 2,
+        width,
+        height,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::history::MIME_TEXT;
+
+    fn item(id: u64, text: &str) -> Item {
+        Item {
+            id,
+            time: now(),
+            direction: Direction::Received,
+            peer: "10.0.0.2".to_string(),
+            mime: MIME_TEXT.to_string(),
+            text: text.to_string(),
+            sensitive: false,
+        }
+    }
+
+    fn press(picker: &mut Picker, code: KeyCode) -> Action {
+        picker.key(KeyEvent::from(code))
+    }
+
+    #[test]
+    fn filter_and_pick() {
+        let items = vec![
+            item(3, "cargo build"),
+            item(2, "hello"),
+            item(1, "git commit"),
+        ];
+        let mut picker = Picker::new(items, vec!["[::1]:9296".to_string()]);
+        assert_eq!(picker.matches, [0, 1, 2]);
+
+        for c in "cmt".chars() {
+            press(&mut picker, KeyCode::Char(c));
+        }
+        assert_eq!(picker.matches, [2]);
+        assert_eq!(press(&mut picker, KeyCode::Enter), Action::Copy(2));
+
+        press(&mut picker, KeyCode::Backspace);
+        press(&mut picker, KeyCode::Backspace);
+        press(&mut picker, KeyCode::Backspace);
+        press(&mut picker, KeyCode::Down);
+        press(&mut picker, KeyCode::Tab);
+        press(&mut picker, KeyCode::Down);
+        press(&mut picker, KeyCode::Down);
+        assert_eq!(
+            press(&mut picker, KeyCode::Enter),
+            Action::Send(1, Some("[::1]:9296".to_string()))
+        );
+        assert_eq!(press(&mut picker, KeyCode::Esc), Action::Quit);
+    }
+}
@@ -0,0 +1,369 @@
+use crate::{
+    app::{
+        history::{Direction, Item},
+        outbox::now,
+    },
+    config::settings::Server,
+    transport::{
+        address::Address,
+        client::{Delivery, DeliveryStatus},
+        control::DaemonStatus,
+        status::Probe,
+    },
+};
+use anyhow::Result;
+
+/// Печатает отчёт о доставке таблицей или JSON
+pub fn print_report(report: &[Delivery], json: bool) -> Result<()> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(report)?);
+    } else {
+        print!("{}", table(report));
+    }
+    Ok(())
+}
+
+/// Состояние демона (если запущен) и результаты опроса устройств
+pub fn print_status(daemon: Option<&DaemonStatus>, probes: &[Probe], json: bool) -> Result<()> {
+    if json {
+        let status = serde_json::json!({ "daemon": daemon, "peers": probes });
+        println!("{}", serde_json::to_string_pretty(&status)?);
+        return Ok(());
+    }
+    match daemon {
+        Some(status) => {
+            println!("bufsy {} (pid {})", status.version, status.pid);
+            println!("device   {}", status.device_id);
+            println!("listen   {}", status.listen);
+            println!("uptime   {}s", status.uptime);
+            println!("outbox   {}", status.outbox);
+            println!("seen     {}", status.seen);
+        }
+        None => println!("Bufsy is not running"),
+    }
+    println!();
+    print!("{}", probe_table(probes));
+    Ok(())
+}
+
+/// Опрос устройств без состояния демона (`bufsy peer test`)
+pub fn print_probes(probes: &[Probe], json: bool) -> Result<()> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(probes)?);
+    } else {
+        print!("{}", probe_table(probes));
+    }
+    Ok(())
+}
+
+fn probe_table(probes: &[Probe]) -> String {
+    if probes.is_empty() {
+        return "No peers\n".to_string();
+    }
+    let peer_width = probes
+        .iter()
+        .map(|p| p.peer.len())
+        .max()
+        .unwrap_or_default()
+        .max("PEER".len());
+    let mut out = format!(
+        "{:peer_width$}  {:11}  {:>8}  {:8}  {:8}  DEVICE\n",
+        "PEER", "STATE", "LATENCY", "VERSION", "KEY"
+    );
+    for probe in probes {
+        let key = match probe.key_match {
+            Some(true) => "match",
+            Some(false) => "MISMATCH",
+            None => "?",
+        };
+        let (version, device) = match &probe.status {
+            Some(status) => (status.version.as_str(), status.device.as_str()),
+            None => ("-", probe.error.as_deref().unwrap_or_default()),
+        };
+        out.push_str(
+            format!(
+                "{:peer_width$}  {:11}  {:>6}ms  {:8}  {:8}  {}",
+                probe.peer,
+                if probe.reachable { "ok" } else { "unreachable" },
+                probe.latency_ms,
+                version,
+                key,
+                device
+            )
+            .trim_end(),
+        );
+        out.push('\n');
+    }
+    out
+}
+
+pub fn print_peers(peers: &[Server], json: bool) -> Result<()> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(peers)?);
+        return Ok(());
+    }
+    if peers.is_empty() {
+        println!("No peers, add one with `bufsy peer add host:port` or `bufsy discover --add`");
+        return Ok(());
+    }
+    let rows: Vec<[String; 4]> = peers
+        .iter()
+        .map(|peer| {
+            [
+                peer.name.clone().unwrap_or("-".to_string()),
+                Address::new(&peer.host, peer.port).to_string(),
+                peer.id.clone().unwrap_or("-".to_string()),
+                if peer.disabled { "disabled" } else { "" }.to_string(),
+            ]
+        })
+        .collect();
+    let name_width = rows
+        .iter()
+        .map(|r| r[0].len())
+        .max()
+        .unwrap_or_default()
+        .max(4);
+    let address_width = rows
+        .iter()
+        .map(|r| r[1].len())
+        .max()
+        .unwrap_or_default()
+        .max(7);
+    println!(
+        "{:name_width$}  {:address_width$}  DEVICE",
+        "NAME", "ADDRESS"
+    );
+    for [name, address, device, state] in rows {
+        println!(
+            "{}",
+            format!("{name:name_width$}  {address:address_width$}  {device:16}  {state}")
+                .trim_end()
+        );
+    }
+    Ok(())
+}
+
+/// Записи истории по строке: id, давность, направление, адрес и начало текста.
+/// В JSON текст секретов пустой, как и в таблице: целиком его даёт только `history show`.
+pub fn print_history(items: &[Item], json: bool) -> Result<()> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(&masked(items))?);
+        return Ok(());
+    }
+    if items.is_empty() {
+        println!("History is empty");
+    }
+    let now = now();
+    for item in items {
+        let direction = match item.direction {
+            Direction::Sent => "to",
+            Direction::Received => "from",
+        };
+        println!(
+            "{:>5}  {} ago  {} {}  {}",
+            item.id,
+            ago(now.saturating_sub(item.time)),
+            direction,
+            item.peer,
+            preview(item)
+        );
+    }
+    Ok(())
+}
+
+fn masked(items: &[Item]) -> Vec<Item> {
+    items
+        .iter()
+        .map(|item| Item {
+            text: if item.sensitive {
+                String::new()
+            } else {
+                item.text.clone()
+            },
+            ..item.clone()
+        })
+        .collect()
+}
+
+/// Строка для лаунчера: `id<TAB>текст` в одну строку, не длиннее 100 символов
+pub fn menu_line(item: &Item) -> String {
+    if item.sensitive {
+        return format!("{}\t(sensitive)", item.id);
+    }
+    let text = item.text.split_whitespace().collect::<Vec<_>>().join(" ");
+    let mut line: String = text.chars().take(100).collect();
+    if line.len() < text.len() {
+        line.push('…');
+    }
+    format!("{}\t{}", item.id, line)
+}
+
+/// id записи из строки, выбранной в `history --menu`
+pub fn menu_id(line: &str) -> Result<u64> {
+    let id = line.split('\t').next().unwrap_or_default().trim();
+    id.parse()
+        .map_err(|_| anyhow::anyhow!("Not a line from `bufsy history --menu`: {}", line.trim()))
+}
+
+pub(super) fn ago(seconds: u64) -> String {
+    match seconds {
+        0..60 => format!("{}s", seconds),
+        60..3600 => format!("{}m", seconds / 60),
+        3600..86400 => format!("{}h", seconds / 3600),
+        _ => format!("{}d", seconds / 86400),
+    }
+}
+
+/// Первая строка текста, не длиннее 60 символов; секреты скрыты
+pub(super) fn preview(item: &Item) -> String {
+    if item.sensitive {
+        return "(sensitive)".to_string();
+    }
+    let line = item.text.lines().next().unwrap_or_default();
+    let mut preview: String = line.chars().take(60).collect();
+    if preview.len() < item.text.len() {
+        preview.push('…');
+    }
+    preview
+}
+
+fn status(delivery: &Delivery) -> String {
+    let status = match delivery.status {
+        DeliveryStatus::Delivered => "delivered",
+        DeliveryStatus::Duplicate => "duplicate",
+        DeliveryStatus::Rejected => "rejected",
+        DeliveryStatus::Failed => "failed",
+        DeliveryStatus::Timeout => "timeout",
+    };
+    if delivery.queued {
+        format!("{} (queued)", status)
+    } else {
+        status.to_string()
+    }
+}
+
+fn table(report: &[Delivery]) -> String {
+    if report.is_empty() {
+        return "No peers to send to\n".to_string();
+    }
+    let peer_width = report
+        .iter()
+        .map(|d| d.peer.len())
+        .max()
+        .unwrap_or_default()
+        .max("PEER".len());
+    let status_width = report
+        .iter()
+        .map(|d| status(d).len())
+        .max()
+        .unwrap_or_default()
+        .max("STATUS".len());
+
+    let mut out = format!(
+        "{:peer_width$}  {:status_width$}  {:>8}  ERROR\n",
+        "PEER", "STATUS", "LATENCY"
+    );
+    for delivery in report {
+        out.push_str(
+            format!(
+                "{:peer_width$}  {:status_width$}  {:>6}ms  {}",
+                delivery.peer,
+                status(delivery),
+                delivery.latency_ms,
+                delivery.error.as_deref().unwrap_or_default()
+            )
+            .trim_end(),
+        );
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn table_columns() {
+        let report = vec![
+            Delivery {
+                peer: "192.168.1.5:9296".to_string(),
+                status: DeliveryStatus::Delivered,
+                latency_ms: 12,
+                error: None,
+                code: None,
+                queued: false,
+            },
+            Delivery {
+                peer: "10.0.0.2:9296".to_string(),
+                status: DeliveryStatus::Timeout,
+                latency_ms: 5000,
+                error: Some("timed out".to_string()),
+                code: None,
+                queued: true,
+            },
+        ];
+
+        assert_eq!(
+            table(&report),
+            "PEER              STATUS             LATENCY  ERROR\n\
+             192.168.1.5:9296  delivered             12ms\n\
+             10.0.0.2:9296     timeout (queued)    5000ms  timed out\n"
+        );
+    }
+
+    #[test]
+    fn menu_roundtrip() {
+        let item = Item {
+            id: 42,
+            time: 0,
+            direction: Direction::Received,
+            peer: "10.0.0.2".to_string(),
+            mime: "text/plain".to_string(),
+            text: "fn main() {\n    println!(\"кот\");\n}".to_string(),
+            sensitive: false,
+        };
+        let line = menu_line(&item);
+        assert_eq!(line, "42\tfn main() { println!(\"кот\"); }");
+        assert_eq!(menu_id(&format!("{}\n", line)).unwrap(), 42);
+        assert!(menu_id("fn main()").is_err());
+
+        let secret = Item {
+            sensitive: true,
+            ..item.clone()
+        };
+        assert_eq!(menu_line(&secret), "42\t(sensitive)");
+        let masked = masked(&[item.clone(), secret]);
+        assert_eq!(masked[0].text, item.text);
+        assert_eq!(masked[1].text, "");
+    }
+
+    #[test]
+    fn probe_table_columns() {
+        let probes = vec![
+            Probe {
+                peer: "[fe80::1%eth0]:9296".to_string(),
+                reachable: true,
+                latency_ms: 3,
+                key_match: Some(false),
+                status: None,
+                error: None,
+            },
+            Probe {
+                peer: "10.0.0.2:9296".to_string(),
+                reachable: false,
+                latency_ms: 1500,
+                key_match: None,
+                status: None,
+                error: Some("connection refused".to_string()),
+            },
+        ];
+
+        assert_eq!(
+            probe_table(&probes),
+            "PEER                 STATE         LATENCY  VERSION   KEY       DEVICE\n\
+             [fe80::1%eth0]:9296  ok                3ms  -         MISMATCH\n\
+             10.0.0.2:9296        unreachable    1500ms  -         ?         connection refused\n"
+        );
+    }
+}
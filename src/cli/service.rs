@@ -0,0 +1,97 @@
+use anyhow::Result;
+use std::{fs, path::Path};
+
+/// Юнит для `systemctl --user`; `Type=notify` — bufsy сам сообщает о готовности
+pub fn service_unit(exe: &str) -> String {
+    format!(
+        "[Unit]
+Description=Bufsy clipboard sync
+After=network-online.target graphical-session.target
+PartOf=graphical-session.target
+
+[Service]
+Type=notify
+NotifyAccess=main
+ExecStart={} run
+ExecReload=/bin/kill -HUP $MAINPID
+Restart=on-failure
+WatchdogSec=30
+
+[Install]
+WantedBy=graphical-session.target
+",
+        quote(exe)
+    )
+}
+
+/// Путь для `ExecStart`: в кавычках, чтобы пробелы не разбивали его на аргументы,
+/// а `%` и `$` не раскрывались systemd
+fn quote(exe: &str) -> String {
+    let escaped = exe
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('%', "%%")
+        .replace('$', "$$");
+    format!("\"{}\"", escaped)
+}
+
+/// Сокет для socket activation: systemd слушает порт и запускает bufsy при подключении
+pub fn socket_unit(port: u16) -> String {
+    format!(
+        "[Unit]
+Description=Bufsy clipboard sync socket
+
+[Socket]
+ListenStream={}
+BindIPv6Only=both
+
+[Install]
+WantedBy=sockets.target
+",
+        port
+    )
+}
+
+/// Записывает юниты в `dir`, возвращает пути записанных файлов
+pub fn install(dir: &Path, exe: &str, port: u16, socket: bool) -> Result<Vec<String>> {
+    fs::create_dir_all(dir)?;
+    let mut written = Vec::new();
+
+    let service = dir.join("bufsy.service");
+    fs::write(&service, service_unit(exe))?;
+    written.push(service.display().to_string());
+
+    if socket {
+        let socket = dir.join("bufsy.socket");
+        fs::write(&socket, socket_unit(port))?;
+        written.push(socket.display().to_string());
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn install_units() {
+        let dir = Path::new("test_dir/systemd");
+        let written = install(dir, "/usr/bin/bufsy", 9296, true).unwrap();
+        assert_eq!(written.len(), 2);
+
+        let service = fs::read_to_string(dir.join("bufsy.service")).unwrap();
+        assert!(service.contains("ExecStart=\"/usr/bin/bufsy\" run\n"));
+        assert!(service.contains("Type=notify\n"));
+        let socket = fs::read_to_string(dir.join("bufsy.socket")).unwrap();
+        assert!(socket.contains("ListenStream=9296\n"));
+    }
+
+    #[test]
+    fn quote_exec_path() {
+        assert_eq!(
+            quote("/home/me/my apps/bufsy"),
+            "\"/home/me/my apps/bufsy\""
+        );
+        assert_eq!(quote("/opt/100%/$bin\"x"), "\"/opt/100%%/$$bin\\\"x\"");
+    }
+}
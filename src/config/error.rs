@@ -0,0 +1,94 @@
+use super::migrate::CONFIG_VERSION;
+use std::{fmt, io};
+
+/// Ошибка чтения или записи `config.toml`, всегда с путём к файлу
+#[derive(Debug)]
+pub enum ConfigError {
+    /// файла нет: `bufsy init` ещё не запускали
+    Missing {
+        path: String,
+    },
+    Read {
+        path: String,
+        source: io::Error,
+    },
+    /// TOML не разбирается или не подходит под настройки; строка и столбец с 1
+    Parse {
+        path: String,
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    /// конфиг от более новой версии bufsy
+    Unsupported {
+        path: String,
+        version: i64,
+    },
+    Write {
+        path: String,
+        source: io::Error,
+    },
+    Serialize {
+        path: String,
+        message: String,
+    },
+}
+
+impl ConfigError {
+    /// Позиция `span` из ошибки `toml` в тексте файла
+    pub fn parse(path: &str, content: &str, error: &toml::de::Error) -> Self {
+        let offset = error.span().map_or(0, |span| span.start).min(content.len());
+        let before = &content[..offset];
+        ConfigError::Parse {
+            path: path.to_string(),
+            line: before.matches('\n').count() + 1,
+            column: before
+                .rsplit('\n')
+                .next()
+                .unwrap_or_default()
+                .chars()
+                .count()
+                + 1,
+            message: error.message().to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Missing { path } => {
+                write!(f, "No config at {}, run `bufsy init` first", path)
+            }
+            ConfigError::Read { path, source } => {
+                write!(f, "Failed to read config {}: {}", path, source)
+            }
+            ConfigError::Parse {
+                path,
+                line,
+                column,
+                message,
+            } => write!(f, "{}:{}:{}: {}", path, line, column, message),
+            ConfigError::Unsupported { path, version } => write!(
+                f,
+                "Config {} has version {}, this bufsy supports up to {}, please upgrade",
+                path, version, CONFIG_VERSION
+            ),
+            ConfigError::Write { path, source } => {
+                write!(f, "Failed to write config {}: {}", path, source)
+            }
+            ConfigError::Serialize { path, message } => {
+                write!(f, "Failed to serialize config {}: {}", path, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigError::Read { source, .. } | ConfigError::Write { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
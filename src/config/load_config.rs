@@ -1,26 +1,152 @@
-use anyhow::Result;
+use crate::config::{
+    error::ConfigError,
+    migrate::{self, CONFIG_VERSION},
+    settings::{Settings, new_device_id},
+};
+use serde::Deserialize;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{ErrorKind, Write},
+    os::{fd::AsRawFd, unix::fs::OpenOptionsExt},
+};
 
-use crate::config::settings::Settings;
-use std::fs;
+/// Читает конфиг. Старую версию обновляет только в памяти, файл не трогает:
+/// на диск её переписывает `upgrade` при запуске команды.
+pub fn load(path: &str) -> Result<Settings, ConfigError> {
+    Ok(read(path)?.0)
+}
 
-pub fn load(path: &str) -> Settings {
+/// Читает конфиг и, если он старой версии, переписывает его на месте. Прежний файл
+/// остаётся рядом в `config.toml.v{N}.bak`, существующие копии не перезаписываются.
+/// Демон и команды делают это под `flock`, так что конфиг обновляется один раз.
+pub fn upgrade(path: &str) -> Result<Settings, ConfigError> {
+    let _lock = lock(path)?;
+    let (config, version) = read(path)?;
+    let Some(version) = version else {
+        return Ok(config);
+    };
     let file_path = format!("{}/config.toml", path);
-    let content = fs::read_to_string(&file_path)
-        .unwrap_or_else(|e| panic!("failed to read config file {}: {}", file_path, e));
-
-    toml::from_str(&content)
-        .unwrap_or_else(|e| panic!("failed to parse config file {}: {}", file_path, e))
+    let backup = backup(&file_path, version)?;
+    save(&config, path)?;
+    tracing::info!(
+        from = version,
+        to = CONFIG_VERSION,
+        backup,
+        "Config migrated"
+    );
+    Ok(config)
 }
 
-pub fn save(config: &Settings, path: &str) -> Result<()> {
+/// Настройки и версия, из которой их обновили (`None` — конфиг уже текущей версии)
+fn read(path: &str) -> Result<(Settings, Option<i64>), ConfigError> {
     let file_path = format!("{}/config.toml", path);
-    let content = toml::to_string_pretty(config)
-        .unwrap_or_else(|e| panic!("failed to serialize config file {}: {}", file_path, e));
+    let content = fs::read_to_string(&file_path).map_err(|e| match e.kind() {
+        ErrorKind::NotFound => ConfigError::Missing {
+            path: file_path.clone(),
+        },
+        _ => ConfigError::Read {
+            path: file_path.clone(),
+            source: e,
+        },
+    })?;
 
-    fs::write(&file_path, content)
-        .unwrap_or_else(|e| panic!("failed to write config file {}: {}", file_path, e));
+    let parse_error = |e: toml::de::Error| ConfigError::parse(&file_path, &content, &e);
+    let mut table: toml::Table = toml::from_str(&content).map_err(parse_error)?;
+    let version = migrate::version(&table);
+    let current = i64::from(CONFIG_VERSION);
+    if version == current {
+        return Ok((toml::from_str(&content).map_err(parse_error)?, None));
+    }
+    if !(0..current).contains(&version) {
+        return Err(ConfigError::Unsupported {
+            path: file_path,
+            version,
+        });
+    }
 
-    Ok(())
+    migrate::migrate(&mut table, version as u32);
+    let config = Settings::deserialize(table).map_err(|e| {
+        // у ошибки в обновлённой таблице нет места в файле, ищем его в исходном тексте
+        parse_error(toml::from_str::<Settings>(&content).err().unwrap_or(e))
+    })?;
+    Ok((config, Some(version)))
+}
+
+/// Копия конфига версии `version` под ещё не занятым именем
+fn backup(file_path: &str, version: i64) -> Result<String, ConfigError> {
+    for attempt in 0.. {
+        let backup = match attempt {
+            0 => format!("{}.v{}.bak", file_path, version),
+            n => format!("{}.v{}.{}.bak", file_path, version, n),
+        };
+        let write_error = |e| ConfigError::Write {
+            path: backup.clone(),
+            source: e,
+        };
+        let mut file = match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&backup)
+        {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(write_error(e)),
+        };
+        let mut original = File::open(file_path).map_err(|e| ConfigError::Read {
+            path: file_path.to_string(),
+            source: e,
+        })?;
+        std::io::copy(&mut original, &mut file).map_err(write_error)?;
+        return Ok(backup);
+    }
+    unreachable!()
+}
+
+/// Конфиг пишут и демон (новые адреса устройств), и команды: load → изменение → save
+/// идут под этой блокировкой, иначе одна из двух правок молча потеряется
+pub fn lock(path: &str) -> Result<File, ConfigError> {
+    let lock_path = format!("{}/config.toml.lock", path);
+    let lock_error = |e| ConfigError::Write {
+        path: lock_path.clone(),
+        source: e,
+    };
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_path)
+        .map_err(lock_error)?;
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+        return Err(lock_error(std::io::Error::last_os_error()));
+    }
+    Ok(file)
+}
+
+pub fn save(config: &Settings, path: &str) -> Result<(), ConfigError> {
+    let file_path = format!("{}/config.toml", path);
+    let content = toml::to_string_pretty(config).map_err(|e| ConfigError::Serialize {
+        path: file_path.clone(),
+        message: e.to_string(),
+    })?;
+
+    // через временный файл: читатель никогда не видит конфиг наполовину записанным.
+    // В конфиге ключ, поэтому 0600
+    let tmp = format!("{}.{}.tmp", file_path, new_device_id());
+    let write_error = |e| ConfigError::Write {
+        path: file_path.clone(),
+        source: e,
+    };
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&tmp)
+        .and_then(|mut file| file.write_all(content.as_bytes()))
+        .and_then(|_| fs::rename(&tmp, &file_path))
+        .inspect_err(|_| {
+            let _ = fs::remove_file(&tmp);
+        })
+        .map_err(write_error)
 }
 
 #[cfg(test)]
@@ -36,18 +162,61 @@ pub mod tests {
         fs::create_dir_all(config_dir).unwrap();
         let config = test_load_config();
         save(&config, config_dir).unwrap();
-        let loaded_config = load(config_dir);
+        let loaded_config = load(config_dir).unwrap();
         assert_eq!(config, loaded_config);
+
+        // файл подменяется целиком и читается только владельцем
+        use std::os::unix::fs::PermissionsExt;
+        let mode = fs::metadata(format!("{}/config.toml", config_dir))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert!(
+            fs::read_dir(config_dir).unwrap().all(|entry| !entry
+                .unwrap()
+                .path()
+                .to_string_lossy()
+                .ends_with(".tmp"))
+        );
+    }
+
+    #[test]
+    fn load_errors() {
+        let error = load("test_dir/no_config").unwrap_err();
+        assert!(matches!(error, ConfigError::Missing { .. }));
+        assert!(error.to_string().contains("bufsy init"));
+
+        let config_dir = "test_dir/broken_config";
+        fs::create_dir_all(config_dir).unwrap();
+        fs::write(
+            format!("{}/config.toml", config_dir),
+            "key = \"00\"\nconnections = []\n\n[server]\nhost = \"::\"\nport = \"9296\"\n",
+        )
+        .unwrap();
+        match load(config_dir).unwrap_err() {
+            ConfigError::Parse { line, column, .. } => assert_eq!((line, column), (6, 8)),
+            error => panic!("unexpected {}", error),
+        }
     }
 
     pub fn test_load_config() -> Settings {
         Settings {
-            server: Server {
-                host: "localhost".to_owned(),
-                port: 8086,
-            },
+            version: CONFIG_VERSION,
+            server: Server::new("localhost", 8086),
             key: "149a44cb0b9a4a56450c1da0cf8f107db8778b7e26c7b95fc4b36b9392c3b67b".to_owned(),
+            device_id: "b0f5c1a2d3e4f607".to_owned(),
             connections: Vec::new(),
+            outbox: Default::default(),
+            client: Default::default(),
+            network: Default::default(),
+            metrics: Default::default(),
+            log: Default::default(),
+            limits: Default::default(),
+            history: Default::default(),
+            gossip: Default::default(),
+            groups: Default::default(),
+            default_to: Default::default(),
             // connections: vec![Server {
             //     host: "localhost".to_owned(),
             //     port: 8086,
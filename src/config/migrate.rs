@@ -0,0 +1,176 @@
+use super::settings::new_device_id;
+use toml::{Table, Value};
+
+/// Версия схемы `config.toml`, которую пишет эта сборка
+pub const CONFIG_VERSION: u32 = 1;
+
+/// Шаг `i` переводит конфиг версии `i` в версию `i + 1`. Новые поля с `serde(default)`
+/// шага не требуют, шаг нужен, когда старые данные надо переписать.
+const MIGRATIONS: [fn(&mut Table); CONFIG_VERSION as usize] = [v0_to_v1];
+
+/// Версия конфига, без поля `version` — 0 (файлы до её появления)
+pub fn version(table: &Table) -> i64 {
+    table
+        .get("version")
+        .and_then(Value::as_integer)
+        .unwrap_or(0)
+}
+
+/// Доводит конфиг версии `from` до `CONFIG_VERSION`
+pub fn migrate(table: &mut Table, from: u32) {
+    for step in &MIGRATIONS[from as usize..] {
+        step(table);
+    }
+    table.insert("version".to_string(), Value::Integer(CONFIG_VERSION.into()));
+}
+
+/// До версий: у устройства нет `device_id`, адреса IPv6 в `connections` могли быть в скобках
+fn v0_to_v1(table: &mut Table) {
+    let empty = table
+        .get("device_id")
+        .and_then(Value::as_str)
+        .is_none_or(str::is_empty);
+    if empty {
+        table.insert("device_id".to_string(), Value::String(new_device_id()));
+    }
+    let connections = table
+        .get_mut("connections")
+        .and_then(Value::as_array_mut)
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_table_mut);
+    for connection in connections {
+        if let Some(Value::String(host)) = connection.get_mut("host")
+            && let Some(bare) = host.strip_prefix('[').and_then(|h| h.strip_suffix(']'))
+        {
+            *host = bare.to_string();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{error::ConfigError, load, upgrade};
+    use std::fs;
+
+    const KEY: &str = "149a44cb0b9a4a56450c1da0cf8f107db8778b7e26c7b95fc4b36b9392c3b67b";
+
+    /// Пишет `content` как конфиг в чистый каталог
+    fn write_config(name: &str, content: &str) -> String {
+        let config_dir = format!("test_dir/migrate_{}", name);
+        let _ = fs::remove_dir_all(&config_dir);
+        fs::create_dir_all(&config_dir).unwrap();
+        fs::write(format!("{}/config.toml", config_dir), content).unwrap();
+        config_dir
+    }
+
+    #[test]
+    fn baseline_schema() {
+        // первая версия: только server, key и connections
+        let config_dir = write_config(
+            "baseline",
+            &format!(
+                "key = \"{KEY}\"\n\n[server]\nhost = \"192.168.1.5\"\nport = 9296\n\n\
+                 [[connections]]\nhost = \"192.168.1.10\"\nport = 9296\n\n\
+                 [[connections]]\nhost = \"[fe80::1]\"\nport = 9296\n"
+            ),
+        );
+        // `load` обновляет только в памяти, файл переписывает `upgrade`
+        assert_eq!(load(&config_dir).unwrap().version, CONFIG_VERSION);
+        assert!(!fs::exists(format!("{}/config.toml.v0.bak", config_dir)).unwrap());
+        let config = upgrade(&config_dir).unwrap();
+        assert_eq!(config.version, CONFIG_VERSION);
+        assert_eq!(config.key, KEY);
+        assert_eq!(config.server.host, "192.168.1.5");
+        assert_eq!(config.device_id.len(), 16);
+        assert_eq!(config.connections[1].host, "fe80::1");
+        assert_eq!(config.history, Default::default());
+
+        let backup = fs::read_to_string(format!("{}/config.toml.v0.bak", config_dir)).unwrap();
+        assert!(!backup.contains("device_id"));
+        let saved = fs::read_to_string(format!("{}/config.toml", config_dir)).unwrap();
+        assert!(saved.starts_with("version = 1"));
+        // второй запуск ничего не переписывает
+        assert_eq!(upgrade(&config_dir).unwrap(), config);
+    }
+
+    #[test]
+    fn keeps_backups() {
+        let content =
+            format!("key = \"{KEY}\"\nconnections = []\n\n[server]\nhost = \"::\"\nport = 9296\n");
+        let config_dir = write_config("backups", &content);
+        let backup = format!("{}/config.toml.v0.bak", config_dir);
+        fs::write(&backup, "earlier").unwrap();
+
+        upgrade(&config_dir).unwrap();
+        assert_eq!(fs::read_to_string(&backup).unwrap(), "earlier");
+        assert_eq!(
+            fs::read_to_string(format!("{}/config.toml.v0.1.bak", config_dir)).unwrap(),
+            content
+        );
+    }
+
+    #[test]
+    fn device_id_schema() {
+        // device_id, id устройств, outbox, таймауты и сеть, но ещё без version
+        let config_dir = write_config(
+            "device_id",
+            &format!(
+                "key = \"{KEY}\"\ndevice_id = \"b0f5c1a2d3e4f607\"\n\n\
+                 [server]\nhost = \"::\"\nport = 9296\n\n\
+                 [[connections]]\nhost = \"192.168.1.10\"\nport = 9296\nid = \"cafe\"\n\n\
+                 [outbox]\nttl = 60\nretry_min = 5\nretry_max = 600\n\n\
+                 [client]\nconnect_timeout_ms = 1500\ntimeout_ms = 5000\n\n\
+                 [network]\nwatch_interval = 0\n"
+            ),
+        );
+        let config = upgrade(&config_dir).unwrap();
+        assert_eq!(config.version, CONFIG_VERSION);
+        assert_eq!(config.device_id, "b0f5c1a2d3e4f607");
+        assert_eq!(config.connections[0].id.as_deref(), Some("cafe"));
+        assert_eq!(config.outbox.ttl, 60);
+        assert_eq!(config.network.watch_interval, 0);
+        assert!(fs::exists(format!("{}/config.toml.v0.bak", config_dir)).unwrap());
+    }
+
+    #[test]
+    fn history_schema() {
+        // метрики, журнал, лимиты и история, последняя схема без version
+        let config_dir = write_config(
+            "history",
+            &format!(
+                "key = \"{KEY}\"\ndevice_id = \"b0f5c1a2d3e4f607\"\nconnections = []\n\n\
+                 [server]\nhost = \"::\"\nport = 9296\n\n\
+                 [metrics]\nenabled = true\n\n[log]\nfile = true\n\n\
+                 [limits]\nmax_body = 1024\nrate = 10\nburst = 30\nmax_decrypts = 16\n\n\
+                 [history]\nenabled = false\nmax_items = 10\nmax_age_days = 1\nexclude_sensitive = true\n"
+            ),
+        );
+        let config = load(&config_dir).unwrap();
+        assert_eq!(config.version, CONFIG_VERSION);
+        assert!(config.metrics.enabled && config.log.file);
+        assert_eq!(config.limits.max_body, 1024);
+        assert!(!config.history.enabled);
+        assert!(config.groups.is_empty());
+    }
+
+    #[test]
+    fn current_and_future_schema() {
+        let config_dir = write_config(
+            "current",
+            &format!(
+                "version = 1\nkey = \"{KEY}\"\ndevice_id = \"b0f5c1a2d3e4f607\"\nconnections = []\n\n\
+                 [server]\nhost = \"::\"\nport = 9296\n"
+            ),
+        );
+        assert_eq!(load(&config_dir).unwrap().version, CONFIG_VERSION);
+        assert!(!fs::exists(format!("{}/config.toml.v0.bak", config_dir)).unwrap());
+
+        let config_dir = write_config("future", "version = 99\n");
+        assert!(matches!(
+            load(&config_dir).unwrap_err(),
+            ConfigError::Unsupported { version: 99, .. }
+        ));
+    }
+}
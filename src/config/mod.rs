@@ -1,5 +1,7 @@
+pub mod error;
 pub mod load_config;
+pub mod migrate;
 pub mod settings;
 
-pub use load_config::{load, save};
+pub use load_config::{load, lock, save, upgrade};
 pub use settings::Settings;
@@ -1,38 +1,628 @@
-use crate::app::encryption::generate_key;
-use local_ip_address::local_ip;
+use super::migrate::CONFIG_VERSION;
+use crate::{app::encryption::generate_key, transport::address::Address};
+use anyhow::{Result, anyhow};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
 pub struct Settings {
+    /// версия схемы, старые конфиги обновляет `config::migrate`
+    #[serde(default)]
+    pub version: u32,
     pub server: Server,
     pub key: String,
+    /// Постоянный идентификатор устройства, создаётся в `bufsy init`
+    #[serde(default)]
+    pub device_id: String,
     pub connections: Vec<Server>,
+    #[serde(default)]
+    pub outbox: OutboxSettings,
+    #[serde(default)]
+    pub client: ClientSettings,
+    #[serde(default)]
+    pub network: NetworkSettings,
+    #[serde(default)]
+    pub metrics: MetricsSettings,
+    #[serde(default)]
+    pub log: LogSettings,
+    #[serde(default)]
+    pub limits: LimitSettings,
+    #[serde(default)]
+    pub history: HistorySettings,
+    #[serde(default)]
+    pub gossip: GossipSettings,
+    /// именованные группы устройств: имена из `connections`, адреса или другие группы
+    #[serde(default)]
+    pub groups: BTreeMap<String, Vec<String>>,
+    #[serde(default)]
+    pub default_to: DefaultTargets,
+}
+
+/// Кому отправлять без `--to`; пусто — всем `connections`
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct DefaultTargets {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub send: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub echo: Vec<String>,
+}
+
+/// Пересылка принятого текста своим устройствам, чтобы он дошёл до тех,
+/// кого отправитель не видит напрямую
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct GossipSettings {
+    /// пересылать чужие сообщения и разрешать пересылку своих
+    pub enabled: bool,
+    /// сколько раз отправленное сообщение могут переслать дальше
+    pub hops: u8,
+}
+
+impl Default for GossipSettings {
+    fn default() -> Self {
+        GossipSettings {
+            enabled: false,
+            hops: 2,
+        }
+    }
+}
+
+/// Зашифрованная история буфера обмена (`bufsy history`)
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct HistorySettings {
+    pub enabled: bool,
+    /// сколько записей хранить, 0 — без ограничения
+    pub max_items: usize,
+    /// сколько дней хранить запись, 0 — без ограничения
+    pub max_age_days: u64,
+    /// не сохранять секреты: `send --sensitive` и пароли из менеджеров паролей
+    pub exclude_sensitive: bool,
+}
+
+impl Default for HistorySettings {
+    fn default() -> Self {
+        HistorySettings {
+            enabled: true,
+            max_items: 1000,
+            max_age_days: 30,
+            exclude_sensitive: true,
+        }
+    }
+}
+
+/// Защита `/text` и `/status` от перегрузки
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct LimitSettings {
+    /// максимальный размер запроса в байтах, больше — 413
+    pub max_body: usize,
+    /// запросов в секунду с одного IP, сверх запаса — 429
+    pub rate: u32,
+    /// запас запросов подряд
+    pub burst: u32,
+    /// одновременных расшифровок, остальным — 503
+    pub max_decrypts: usize,
+}
+
+impl Default for LimitSettings {
+    fn default() -> Self {
+        LimitSettings {
+            max_body: 8 * 1024 * 1024,
+            rate: 10,
+            burst: 30,
+            max_decrypts: 16,
+        }
+    }
+}
+
+/// Журнал; уровень задаётся флагами `-v`/`-q` или `RUST_LOG`
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct LogSettings {
+    /// дописывать журнал в `bufsy.log` рядом с конфигом
+    pub file: bool,
+}
+
+/// `/metrics` в формате Prometheus, без авторизации — включать только в доверенной сети
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct MetricsSettings {
+    pub enabled: bool,
+}
+
+/// Сеть для `bufsy run`
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct NetworkSettings {
+    /// слушать только этот интерфейс (`eth0`, `wlan0`), иначе все
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interface: Option<String>,
+    /// как часто проверять смену адресов, в секундах; 0 — не следить
+    pub watch_interval: u64,
+}
+
+impl Default for NetworkSettings {
+    fn default() -> Self {
+        NetworkSettings {
+            interface: None,
+            watch_interval: 10,
+        }
+    }
+}
+
+/// Таймауты отправки, в миллисекундах
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct ClientSettings {
+    pub connect_timeout_ms: u64,
+    /// на весь запрос к одному устройству
+    pub timeout_ms: u64,
+}
+
+impl Default for ClientSettings {
+    fn default() -> Self {
+        ClientSettings {
+            connect_timeout_ms: 1500,
+            timeout_ms: 5000,
+        }
+    }
+}
+
+/// Очередь недоставленных сообщений, все значения в секундах
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct OutboxSettings {
+    /// сколько хранить сообщение, прежде чем выбросить
+    pub ttl: u64,
+    /// первая пауза между попытками, дальше она удваивается
+    pub retry_min: u64,
+    pub retry_max: u64,
+}
+
+impl OutboxSettings {
+    /// Экспоненциальная пауза после `attempts` неудачных попыток
+    pub fn backoff(&self, attempts: u32) -> u64 {
+        let factor = 1u64
+            .checked_shl(attempts.saturating_sub(1))
+            .unwrap_or(u64::MAX);
+        self.retry_min.saturating_mul(factor).min(self.retry_max)
+    }
+}
+
+impl Default for OutboxSettings {
+    fn default() -> Self {
+        OutboxSettings {
+            ttl: 24 * 60 * 60,
+            retry_min: 5,
+            retry_max: 10 * 60,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
 pub struct Server {
     pub host: String,
     pub port: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// имя для `--to`: `laptop`, `phone`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// не отправлять, пока не включат снова (`bufsy peer enable`)
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub disabled: bool,
 }
 
-impl Settings {
-    pub fn new_connection(&mut self, host: &str, port: u16) {
-        self.connections.push(Server {
+impl Server {
+    pub fn new(host: &str, port: u16) -> Self {
+        Server {
             host: host.to_string(),
             port,
-        });
+            id: None,
+            name: None,
+            disabled: false,
+        }
+    }
+
+    /// `peer` (имя, id или адрес) указывает на это устройство
+    pub fn is(&self, peer: &str) -> bool {
+        self.name.as_deref() == Some(peer)
+            || self.id.as_deref() == Some(peer)
+            || peer
+                .parse::<Address>()
+                .is_ok_and(|a| a.host == self.host && a.port == self.port)
     }
 }
 
+impl Settings {
+    pub fn new_connection(&mut self, host: &str, port: u16) -> &mut Server {
+        self.connections.push(Server::new(host, port));
+        self.connections.last_mut().unwrap()
+    }
+
+    pub fn has_connection(&self, host: &str, port: u16) -> bool {
+        self.connections
+            .iter()
+            .any(|c| c.host == host && c.port == port)
+    }
+
+    /// Запоминает устройство: известное по `id` переезжает на новый адрес,
+    /// а не добавляется второй раз. Возвращает `true`, если `connections` изменились.
+    pub fn upsert_connection(&mut self, id: Option<&str>, host: &str, port: u16) -> bool {
+        if let Some(id) = id
+            && let Some(known) = self
+                .connections
+                .iter_mut()
+                .find(|c| c.id.as_deref() == Some(id))
+        {
+            if known.host == host && known.port == port {
+                return false;
+            }
+            known.host = host.to_string();
+            known.port = port;
+            // старая запись на этом адресе теперь указывает на то же устройство
+            self.connections
+                .retain(|c| c.id.as_deref() == Some(id) || c.host != host || c.port != port);
+            return true;
+        }
+
+        if let Some(known) = self
+            .connections
+            .iter_mut()
+            .find(|c| c.host == host && c.port == port)
+        {
+            if id.is_some() && known.id.as_deref() != id {
+                known.id = id.map(str::to_string);
+                return true;
+            }
+            return false;
+        }
+
+        self.new_connection(host, port).id = id.map(str::to_string);
+        true
+    }
+
+    /// Что в настройках не так, пусто — всё в порядке (`bufsy config check`)
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        // сам ключ не выводится: `config check` могут прислать в issue
+        if self.key.len() != 64 {
+            problems.push(format!(
+                "key must be 64 hex characters (32 bytes), got {} characters",
+                self.key.len()
+            ));
+        } else if hex::decode(&self.key).is_err() {
+            problems.push(
+                "key must be 64 hex characters (32 bytes), got non-hex characters".to_string(),
+            );
+        }
+        if self.server.port == 0 {
+            problems.push("server.port must not be 0".to_string());
+        }
+        for (index, c) in self.connections.iter().enumerate() {
+            let address = Address::new(&c.host, c.port).to_string();
+            if c.port == 0
+                || address.parse::<Address>().is_err()
+                || c.host.contains(['[', ']', ' '])
+            {
+                problems.push(format!(
+                    "connections[{}]: invalid address {}",
+                    index, address
+                ));
+            } else if self.connections[..index]
+                .iter()
+                .any(|other| other.host == c.host && other.port == c.port)
+            {
+                problems.push(format!(
+                    "connections[{}]: {} is listed twice",
+                    index, address
+                ));
+            }
+            if let Some(name) = &c.name
+                && let Err(e) = self.check_name(name, Some(index))
+            {
+                problems.push(format!("connections[{}]: {}", index, e));
+            }
+        }
+        for (group, members) in &self.groups {
+            if let Err(e) = self.recipients(members) {
+                problems.push(format!("groups.{}: {}", group, e));
+            }
+        }
+        for (command, targets) in [
+            ("send", &self.default_to.send),
+            ("echo", &self.default_to.echo),
+        ] {
+            if let Err(e) = self.recipients(targets) {
+                problems.push(format!("default_to.{}: {}", command, e));
+            }
+        }
+        problems
+    }
+
+    /// Индекс устройства в `connections` по имени, id или адресу
+    pub fn find_connection(&self, peer: &str) -> Result<usize> {
+        self.connections
+            .iter()
+            .position(|c| c.is(peer))
+            .ok_or_else(|| anyhow!("No peer {}, see `bufsy peer list`", peer))
+    }
+
+    /// Записи групп и `default_to`, для которых `matches` истинно, заменяет на `to`,
+    /// а без `to` убирает. Возвращает, где они были: `groups.home`, `default_to.send`.
+    pub fn rewrite_targets(
+        &mut self,
+        matches: impl Fn(&str) -> bool,
+        to: Option<&str>,
+    ) -> Vec<String> {
+        let lists = self
+            .groups
+            .iter_mut()
+            .map(|(group, members)| (format!("groups.{}", group), members))
+            .chain([
+                ("default_to.send".to_string(), &mut self.default_to.send),
+                ("default_to.echo".to_string(), &mut self.default_to.echo),
+            ]);
+        let mut places = Vec::new();
+        for (place, targets) in lists {
+            if !targets.iter().any(|target| matches(target)) {
+                continue;
+            }
+            match to {
+                Some(to) => targets
+                    .iter_mut()
+                    .filter(|target| matches(target))
+                    .for_each(|target| *target = to.to_string()),
+                None => targets.retain(|target| !matches(target)),
+            }
+            places.push(place);
+        }
+        places
+    }
+
+    /// Имя для `connections[index]`: не пустое, не адрес, не занято группой или другим устройством
+    pub fn check_name(&self, name: &str, index: Option<usize>) -> Result<()> {
+        if name.is_empty() || name.contains([',', ' ']) || name.parse::<Address>().is_ok() {
+            return Err(anyhow!("Invalid peer name {:?}", name));
+        }
+        if self.groups.contains_key(name) {
+            return Err(anyhow!("{} is already a group", name));
+        }
+        if self
+            .connections
+            .iter()
+            .enumerate()
+            .any(|(i, c)| Some(i) != index && c.name.as_deref() == Some(name))
+        {
+            return Err(anyhow!("{} is already taken by another peer", name));
+        }
+        Ok(())
+    }
+
+    /// Адреса получателей: группы раскрываются, имена и id устройств заменяются
+    /// адресами из `connections`. Пустой список — все `connections`.
+    /// Выключенные устройства пропускаются, если их не назвали прямо.
+    pub fn recipients(&self, targets: &[String]) -> Result<Vec<String>> {
+        if targets.is_empty() {
+            return Ok(self
+                .connections
+                .iter()
+                .filter(|c| !c.disabled)
+                .map(|c| Address::new(&c.host, c.port).to_string())
+                .collect());
+        }
+        let mut peers = Vec::new();
+        for target in targets {
+            self.expand(target, &mut Vec::new(), &mut peers)?;
+        }
+        Ok(peers)
+    }
+
+    fn expand<'a>(
+        &'a self,
+        target: &'a str,
+        groups: &mut Vec<&'a str>,
+        peers: &mut Vec<String>,
+    ) -> Result<()> {
+        let mut push = |peer: String| {
+            if !peers.contains(&peer) {
+                peers.push(peer);
+            }
+        };
+        if let Some(members) = self.groups.get(target) {
+            if groups.contains(&target) {
+                return Err(anyhow!("Group {} includes itself", target));
+            }
+            groups.push(target);
+            for member in members {
+                self.expand(member, groups, peers)?;
+            }
+            groups.pop();
+        } else if let Some(c) = self
+            .connections
+            .iter()
+            .find(|c| c.name.as_deref() == Some(target) || c.id.as_deref() == Some(target))
+        {
+            if !c.disabled || groups.is_empty() {
+                push(Address::new(&c.host, c.port).to_string());
+            }
+        } else if let Ok(address) = target.parse::<Address>() {
+            push(address.to_string());
+        } else {
+            return Err(anyhow!("Unknown peer or group {}", target));
+        }
+        Ok(())
+    }
+}
+
+pub fn new_device_id() -> String {
+    hex::encode(&generate_key()[..8])
+}
+
 impl Default for Settings {
     fn default() -> Self {
         Settings {
-            server: Server {
-                host: String::from(local_ip().unwrap().to_string()),
-                port: 9296,
-            },
+            version: CONFIG_VERSION,
+            // dual-stack: принимает и IPv4, и IPv6 на всех интерфейсах
+            server: Server::new("::", 9296),
             key: hex::encode(generate_key()),
+            device_id: new_device_id(),
+            connections: Vec::new(),
+            outbox: OutboxSettings::default(),
+            client: ClientSettings::default(),
+            network: NetworkSettings::default(),
+            metrics: MetricsSettings::default(),
+            log: LogSettings::default(),
+            limits: LimitSettings::default(),
+            history: HistorySettings::default(),
+            gossip: GossipSettings::default(),
+            groups: BTreeMap::new(),
+            default_to: DefaultTargets::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> Settings {
+        Settings {
+            version: CONFIG_VERSION,
+            server: Server::new("127.0.0.1", 9296),
+            key: String::new(),
+            device_id: new_device_id(),
             connections: Vec::new(),
+            outbox: OutboxSettings::default(),
+            client: ClientSettings::default(),
+            network: NetworkSettings::default(),
+            metrics: MetricsSettings::default(),
+            log: LogSettings::default(),
+            limits: LimitSettings::default(),
+            history: HistorySettings::default(),
+            gossip: GossipSettings::default(),
+            groups: BTreeMap::new(),
+            default_to: DefaultTargets::default(),
         }
     }
+
+    #[test]
+    fn recipients_by_name_and_group() {
+        let mut config = settings();
+        config.new_connection("192.168.1.10", 9296).name = Some("laptop".to_string());
+        config.upsert_connection(Some("cafe"), "192.168.1.11", 9296);
+        config.new_connection("10.0.0.2", 9296);
+        config.groups = BTreeMap::from([
+            (
+                "work".to_string(),
+                vec!["laptop".to_string(), "cafe".to_string()],
+            ),
+            (
+                "all".to_string(),
+                vec!["work".to_string(), "[::1]:9296".to_string()],
+            ),
+            ("loop".to_string(), vec!["loop".to_string()]),
+        ]);
+
+        assert_eq!(config.recipients(&[]).unwrap().len(), 3);
+        assert_eq!(
+            config.recipients(&["laptop".to_string()]).unwrap(),
+            ["192.168.1.10:9296"]
+        );
+        assert_eq!(
+            config
+                .recipients(&["all".to_string(), "laptop".to_string()])
+                .unwrap(),
+            ["192.168.1.10:9296", "192.168.1.11:9296", "[::1]:9296"]
+        );
+        assert!(config.recipients(&["phone".to_string()]).is_err());
+        assert!(config.recipients(&["loop".to_string()]).is_err());
+
+        config.connections[0].disabled = true;
+        assert_eq!(config.recipients(&[]).unwrap().len(), 2);
+        assert_eq!(config.recipients(&["work".to_string()]).unwrap().len(), 1);
+        assert_eq!(config.recipients(&["laptop".to_string()]).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn config_problems() {
+        let mut config = settings();
+        config.key = hex::encode(generate_key());
+        assert!(config.problems().is_empty());
+
+        config.key = "cafe".to_string();
+        config.new_connection("192.168.1.10", 9296).name = Some("laptop".to_string());
+        config.new_connection("192.168.1.10", 9296);
+        config.new_connection("bad host", 0);
+        config
+            .groups
+            .insert("work".to_string(), vec!["phone".to_string()]);
+        assert_eq!(
+            config.problems(),
+            [
+                "key must be 64 hex characters (32 bytes), got 4 characters",
+                "connections[1]: 192.168.1.10:9296 is listed twice",
+                "connections[2]: invalid address bad host:0",
+                "groups.work: Unknown peer or group phone",
+            ]
+        );
+
+        config.key = "z".repeat(64);
+        assert!(!config.problems()[0].contains("zz"));
+    }
+
+    #[test]
+    fn find_and_name_peers() {
+        let mut config = settings();
+        config.new_connection("192.168.1.10", 9296).name = Some("laptop".to_string());
+        config.upsert_connection(Some("cafe"), "::1", 9296);
+        config.groups.insert("work".to_string(), Vec::new());
+
+        assert_eq!(config.find_connection("laptop").unwrap(), 0);
+        assert_eq!(config.find_connection("cafe").unwrap(), 1);
+        assert_eq!(config.find_connection("[::1]:9296").unwrap(), 1);
+        assert!(config.find_connection("192.168.1.10:9297").is_err());
+
+        assert!(config.check_name("phone", None).is_ok());
+        assert!(config.check_name("laptop", Some(0)).is_ok());
+        assert!(config.check_name("laptop", Some(1)).is_err());
+        assert!(config.check_name("work", None).is_err());
+        assert!(config.check_name("10.0.0.1:9296", None).is_err());
+        assert!(config.check_name("", None).is_err());
+    }
+
+    #[test]
+    fn upsert_moves_known_device() {
+        let mut config = settings();
+        assert!(config.upsert_connection(Some("cat"), "192.168.1.10", 9296));
+        assert!(!config.upsert_connection(Some("cat"), "192.168.1.10", 9296));
+        assert!(config.upsert_connection(Some("cat"), "192.168.1.27", 9296));
+
+        assert_eq!(config.connections.len(), 1);
+        assert_eq!(config.connections[0].host, "192.168.1.27");
+    }
+
+    #[test]
+    fn upsert_adopts_address_without_id() {
+        let mut config = settings();
+        config.new_connection("192.168.1.10", 9296);
+        assert!(config.upsert_connection(Some("cat"), "192.168.1.10", 9296));
+        assert!(!config.upsert_connection(None, "192.168.1.10", 9296));
+
+        assert_eq!(config.connections.len(), 1);
+        assert_eq!(config.connections[0].id.as_deref(), Some("cat"));
+    }
+
+    #[test]
+    fn upsert_merges_duplicate_address() {
+        let mut config = settings();
+        config.new_connection("192.168.1.27", 9296);
+        config.upsert_connection(Some("cat"), "192.168.1.10", 9296);
+        assert!(config.upsert_connection(Some("cat"), "192.168.1.27", 9296));
+
+        assert_eq!(config.connections.len(), 1);
+        assert_eq!(config.connections[0].id.as_deref(), Some("cat"));
+    }
 }
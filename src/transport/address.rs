@@ -0,0 +1,302 @@
+use anyhow::{Result, anyhow};
+use std::{
+    ffi::{CStr, CString},
+    fmt,
+    net::{IpAddr, Ipv6Addr, SocketAddr, SocketAddrV6},
+    str::FromStr,
+};
+
+/// Суффикс имён, под которыми в URL прячутся IPv6 адреса с зоной (`fe80::1%eth0`):
+/// URL не умеет хранить зону, поэтому адрес кодируется в имя и раскрывается в [`Resolver`].
+/// Имя интерфейса идёт в hex: имена хостов приводятся к нижнему регистру, а `Wlan0`
+/// и `wlan0` — разные интерфейсы.
+const SCOPED_SUFFIX: &str = ".scoped.bufsy";
+
+/// Адрес устройства: `host:port`, `1.2.3.4:port`, `[::1]:port` или `[fe80::1%eth0]:port`
+///
+/// `host` хранится без квадратных скобок, в таком виде он попадает в `connections`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Address {
+    pub host: String,
+    pub port: u16,
+}
+
+impl Address {
+    pub fn new(host: &str, port: u16) -> Self {
+        Self {
+            host: host
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .to_string(),
+            port,
+        }
+    }
+
+    /// IPv6 адрес с зоной: `fe80::1%eth0` → (`fe80::1`, `eth0`)
+    fn scoped(&self) -> Option<(Ipv6Addr, &str)> {
+        let (ip, scope) = self.host.split_once('%')?;
+        Some((ip.parse().ok()?, scope))
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        let host = if let Some((ip, scope)) = self.scoped() {
+            format!(
+                "{}.{}{}",
+                ip.to_string().replace(':', "-"),
+                hex::encode(scope),
+                SCOPED_SUFFIX
+            )
+        } else if self.host.contains(':') {
+            format!("[{}]", self.host)
+        } else {
+            self.host.clone()
+        };
+        format!("http://{}:{}{}", host, self.port, path)
+    }
+
+    /// Адреса для подключения или `bind`; имена хостов разрешаются через DNS
+    pub async fn resolve(&self) -> Result<Vec<SocketAddr>> {
+        if let Ok(ip) = self.host.parse::<IpAddr>() {
+            return Ok(vec![SocketAddr::new(ip, self.port)]);
+        }
+        if let Some((ip, scope)) = self.scoped() {
+            let scope_id =
+                scope_index(scope).ok_or_else(|| anyhow!("Unknown interface {}", scope))?;
+            return Ok(vec![SocketAddr::V6(SocketAddrV6::new(
+                ip, self.port, 0, scope_id,
+            ))]);
+        }
+        let addrs: Vec<SocketAddr> = tokio::net::lookup_host((self.host.as_str(), self.port))
+            .await
+            .map_err(|e| anyhow!("Failed to resolve {}: {}", self.host, e))?
+            .collect();
+        if addrs.is_empty() {
+            return Err(anyhow!("Failed to resolve {}", self.host));
+        }
+        Ok(addrs)
+    }
+}
+
+impl FromStr for Address {
+    type Err = anyhow::Error;
+
+    fn from_str(address: &str) -> Result<Self> {
+        let (host, port) = if let Some(rest) = address.strip_prefix('[') {
+            let (host, port) = rest
+                .split_once("]:")
+                .ok_or_else(|| anyhow!("Invalid address format {}", address))?;
+            (host, port)
+        } else {
+            let (host, port) = address
+                .rsplit_once(':')
+                .ok_or_else(|| anyhow!("Invalid address format {}", address))?;
+            if host.contains(':') {
+                return Err(anyhow!(
+                    "Invalid address format {}, write IPv6 as [addr]:port",
+                    address
+                ));
+            }
+            (host, port)
+        };
+        if host.is_empty() {
+            return Err(anyhow!("Invalid address format {}", address));
+        }
+        let port = port
+            .parse::<u16>()
+            .map_err(|_| anyhow!("Invalid port in {}", address))?;
+        Ok(Self::new(host, port))
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.host.contains(':') {
+            write!(f, "[{}]:{}", self.host, self.port)
+        } else {
+            write!(f, "{}:{}", self.host, self.port)
+        }
+    }
+}
+
+/// Имя хоста отправителя: IPv4 из dual-stack сокета без `::ffff:`, IPv6 с зоной
+pub fn peer_host(addr: &SocketAddr) -> String {
+    match addr {
+        SocketAddr::V6(v6) if v6.ip().to_ipv4_mapped().is_some() => {
+            v6.ip().to_canonical().to_string()
+        }
+        SocketAddr::V6(v6) if v6.scope_id() != 0 => match scope_name(v6.scope_id()) {
+            Some(name) => format!("{}%{}", v6.ip(), name),
+            None => format!("{}%{}", v6.ip(), v6.scope_id()),
+        },
+        _ => addr.ip().to_string(),
+    }
+}
+
+/// Адреса этой машины (без loopback), только интерфейса `interface`, если он задан
+pub fn local_addresses(interface: Option<&str>) -> Vec<IpAddr> {
+    let mut addresses: Vec<IpAddr> = local_ip_address::list_afinet_netifas()
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|(name, ip)| match interface {
+            Some(interface) => name == interface,
+            None => !ip.is_loopback(),
+        })
+        .map(|(_, ip)| ip)
+        .collect();
+    addresses.sort();
+    addresses.dedup();
+    addresses
+}
+
+fn scope_index(scope: &str) -> Option<u32> {
+    if let Ok(index) = scope.parse::<u32>() {
+        return Some(index);
+    }
+    let name = CString::new(scope).ok()?;
+    // SAFETY: `name` — корректная C-строка, живущая до конца вызова
+    let index = unsafe { libc::if_nametoindex(name.as_ptr()) };
+    (index != 0).then_some(index)
+}
+
+fn scope_name(index: u32) -> Option<String> {
+    let mut buf = [0 as libc::c_char; libc::IF_NAMESIZE];
+    // SAFETY: буфер размера IF_NAMESIZE, как требует if_indextoname
+    let name = unsafe { libc::if_indextoname(index, buf.as_mut_ptr()) };
+    if name.is_null() {
+        return None;
+    }
+    // SAFETY: при успехе в буфере лежит строка с нулём на конце
+    let name = unsafe { CStr::from_ptr(buf.as_ptr()) };
+    Some(name.to_string_lossy().to_string())
+}
+
+/// DNS для reqwest: раскрывает имена из [`Address::url`], остальное — системный резолвер
+pub struct Resolver;
+
+impl reqwest::dns::Resolve for Resolver {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        let name = name.as_str().to_string();
+        Box::pin(async move {
+            let address = unscope(&name)?;
+            let addrs: reqwest::dns::Addrs = Box::new(address.resolve().await?.into_iter());
+            Ok(addrs)
+        })
+    }
+}
+
+/// Адрес из имени хоста, обратное к [`Address::url`]
+fn unscope(name: &str) -> Result<Address> {
+    // имена хостов не различают регистр, hex тоже
+    let name = name.to_ascii_lowercase();
+    let Some(scoped) = name.strip_suffix(SCOPED_SUFFIX) else {
+        return Ok(Address::new(&name, 0));
+    };
+    let invalid = || anyhow!("Invalid scoped name {}", name);
+    let (ip, scope) = scoped.split_once('.').ok_or_else(invalid)?;
+    let scope = String::from_utf8(hex::decode(scope).map_err(|_| invalid())?)?;
+    Ok(Address::new(
+        &format!("{}%{}", ip.replace('-', ":"), scope),
+        0,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, SocketAddrV4};
+
+    #[test]
+    fn parse_forms() {
+        let address: Address = "192.168.1.5:9296".parse().unwrap();
+        assert_eq!(address, Address::new("192.168.1.5", 9296));
+        let address: Address = "laptop.local:9296".parse().unwrap();
+        assert_eq!(address, Address::new("laptop.local", 9296));
+        let address: Address = "[::1]:9296".parse().unwrap();
+        assert_eq!(address, Address::new("::1", 9296));
+        let address: Address = "[fe80::1%eth0]:9296".parse().unwrap();
+        assert_eq!(address.host, "fe80::1%eth0");
+        assert_eq!(address.to_string(), "[fe80::1%eth0]:9296");
+
+        assert!("fe80::1:9296".parse::<Address>().is_err());
+        assert!("192.168.1.5".parse::<Address>().is_err());
+        assert!(":9296".parse::<Address>().is_err());
+        assert!("[::1]9296".parse::<Address>().is_err());
+        assert!("host:cat".parse::<Address>().is_err());
+    }
+
+    #[test]
+    fn urls() {
+        assert_eq!(
+            Address::new("192.168.1.5", 9296).url("/text"),
+            "http://192.168.1.5:9296/text"
+        );
+        assert_eq!(
+            Address::new("::1", 9296).url("/text"),
+            "http://[::1]:9296/text"
+        );
+        assert_eq!(
+            Address::new("fe80::1%eth0", 9296).url("/text"),
+            "http://fe80--1.65746830.scoped.bufsy:9296/text"
+        );
+
+        // регистр имени интерфейса переживает приведение имени хоста к нижнему регистру
+        let url = Address::new("fe80::1%Wlan0", 9296).url("/");
+        let host = url
+            .trim_start_matches("http://")
+            .trim_end_matches(":9296/")
+            .to_uppercase();
+        assert_eq!(unscope(&host).unwrap().host, "fe80::1%Wlan0");
+        assert!(unscope("fe80--1.eth0.scoped.bufsy").is_err());
+        assert_eq!(unscope("laptop.local").unwrap().host, "laptop.local");
+    }
+
+    #[test]
+    fn peer_hosts() {
+        let v4 = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(192, 168, 1, 5), 1));
+        assert_eq!(peer_host(&v4), "192.168.1.5");
+        let mapped = SocketAddr::new(
+            IpAddr::V6(Ipv4Addr::new(192, 168, 1, 5).to_ipv6_mapped()),
+            1,
+        );
+        assert_eq!(peer_host(&mapped), "192.168.1.5");
+        let lo = scope_index("lo").unwrap();
+        let scoped = SocketAddr::V6(SocketAddrV6::new("fe80::1".parse().unwrap(), 1, 0, lo));
+        assert_eq!(peer_host(&scoped), "fe80::1%lo");
+    }
+
+    #[test]
+    fn loopback_addresses() {
+        assert!(local_addresses(Some("lo")).contains(&IpAddr::V4(Ipv4Addr::LOCALHOST)));
+        assert!(!local_addresses(None).iter().any(|ip| ip.is_loopback()));
+        assert!(local_addresses(Some("nosuchif0")).is_empty());
+    }
+
+    #[tokio::test]
+    async fn resolve_names() {
+        let addrs = Address::new("localhost", 9296).resolve().await.unwrap();
+        assert!(
+            addrs
+                .iter()
+                .all(|a| a.ip().is_loopback() && a.port() == 9296)
+        );
+
+        let addrs = Address::new("fe80::1%lo", 9296).resolve().await.unwrap();
+        let SocketAddr::V6(v6) = addrs[0] else {
+            panic!("expected IPv6");
+        };
+        assert_eq!(v6.scope_id(), scope_index("lo").unwrap());
+
+        assert!(
+            Address::new("fe80::1%nosuchif0", 1)
+                .resolve()
+                .await
+                .is_err()
+        );
+        assert!(
+            Address::new("nosuchhost.invalid", 1)
+                .resolve()
+                .await
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,153 @@
+use crate::{
+    config::settings::LimitSettings,
+    transport::error::{ApiError, ErrorCode},
+};
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex, PoisonError},
+    time::Instant,
+};
+use tokio::sync::Semaphore;
+
+/// Сколько адресов помнить: сверх этого выбрасываются полные корзины, а если таких нет,
+/// то самая давняя
+const MAX_BUCKETS: usize = 4096;
+
+/// Token bucket на каждый IP и семафор на одновременные расшифровки
+pub struct Limits {
+    settings: LimitSettings,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+    decrypts: Arc<Semaphore>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Limits {
+    pub fn new(settings: LimitSettings) -> Self {
+        Self {
+            decrypts: Arc::new(Semaphore::new(settings.max_decrypts.max(1))),
+            settings,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn max_body(&self) -> usize {
+        self.settings.max_body
+    }
+
+    /// Забирает жетон из корзины `ip`; `false` — запас исчерпан
+    fn allow(&self, ip: IpAddr, now: Instant) -> bool {
+        let rate = self.settings.rate as f64;
+        let burst = self.settings.burst.max(1) as f64;
+        let tokens = |bucket: &Bucket| {
+            let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+            (bucket.tokens + elapsed * rate).min(burst)
+        };
+
+        let mut buckets = self.buckets.lock().unwrap_or_else(PoisonError::into_inner);
+        if buckets.len() >= MAX_BUCKETS && !buckets.contains_key(&ip) {
+            buckets.retain(|_, bucket| tokens(bucket) < burst);
+            // поток с множества адресов не должен раздувать таблицу без предела
+            if buckets.len() >= MAX_BUCKETS
+                && let Some(oldest) = buckets
+                    .iter()
+                    .min_by_key(|(_, bucket)| bucket.updated)
+                    .map(|(ip, _)| *ip)
+            {
+                buckets.remove(&oldest);
+            }
+        }
+        let bucket = buckets.entry(ip).or_insert(Bucket {
+            tokens: burst,
+            updated: now,
+        });
+        bucket.tokens = tokens(bucket);
+        bucket.updated = now;
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+}
+
+/// 429, если адрес шлёт чаще `rate` запросов в секунду сверх запаса `burst`
+pub async fn rate_limit(
+    State(limits): State<Arc<Limits>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if !limits.allow(addr.ip(), Instant::now()) {
+        tracing::debug!(peer = %addr.ip(), "Rate limited");
+        return ApiError::new(ErrorCode::RateLimited, "Too many requests").into_response();
+    }
+    next.run(request).await
+}
+
+/// 503, пока идут `max_decrypts` расшифровок: лишние запросы не копятся в памяти
+pub async fn decrypt_limit(
+    State(limits): State<Arc<Limits>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Ok(_permit) = limits.decrypts.clone().try_acquire_owned() else {
+        return ApiError::new(ErrorCode::Busy, "Too many messages in progress").into_response();
+    };
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn token_bucket() {
+        let limits = Limits::new(LimitSettings {
+            rate: 2,
+            burst: 3,
+            ..Default::default()
+        });
+        let ip: IpAddr = "10.0.0.2".parse().unwrap();
+        let now = Instant::now();
+
+        assert!((0..3).all(|_| limits.allow(ip, now)));
+        assert!(!limits.allow(ip, now));
+        // у другого адреса своя корзина
+        assert!(limits.allow("10.0.0.3".parse().unwrap(), now));
+        // за полсекунды при rate = 2 набегает ровно один жетон
+        let later = now + Duration::from_millis(500);
+        assert!(limits.allow(ip, later));
+        assert!(!limits.allow(ip, later));
+    }
+
+    #[test]
+    fn bounded_buckets() {
+        let limits = Limits::new(LimitSettings {
+            rate: 0,
+            burst: 1,
+            ..Default::default()
+        });
+        let now = Instant::now();
+        let ip = |n: usize| IpAddr::from([10, 1, (n >> 8) as u8, n as u8]);
+
+        // ни одна корзина не полна, но таблица всё равно не растёт
+        for n in 0..=MAX_BUCKETS {
+            assert!(limits.allow(ip(n), now + Duration::from_millis(n as u64)));
+        }
+        let buckets = limits.buckets.lock().unwrap();
+        assert_eq!(buckets.len(), MAX_BUCKETS);
+        assert!(!buckets.contains_key(&ip(0)));
+        assert!(buckets.contains_key(&ip(MAX_BUCKETS)));
+    }
+}
@@ -1,3 +1,4 @@
+mod limits;
 pub mod server;
 
 pub use server::ServerAXUM;
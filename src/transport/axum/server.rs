@@ -1,115 +1,504 @@
-use crate::config::Settings;
+use super::limits::{self, Limits};
+use crate::{
+    app::{
+        encryption::{fingerprint, mac},
+        envelope::{Envelope, Kind},
+        handler::{Message, MessageHandler},
+        history::{History, Item},
+        metrics::Metrics,
+        outbox::now,
+        session::Session,
+    },
+    config::Settings,
+    transport::{
+        address::{Address, peer_host},
+        error::{ApiError, ErrorCode},
+        status::NodeStatus,
+    },
+};
 use anyhow::Result;
 use axum::{
     Router,
-    extract::{ConnectInfo, State},
+    extract::{ConnectInfo, DefaultBodyLimit, FromRef, Query, State, rejection::StringRejection},
+    http::{HeaderMap, header},
+    middleware,
     {routing::get, routing::post},
 };
-use sha2::{Digest, Sha224};
-use std::{collections::HashSet, net::SocketAddr, sync::Arc};
-use tokio::sync::RwLock;
+use serde::{Deserialize, Serialize};
+use socket2::{Domain, Socket, Type};
+use std::{net::SocketAddr, sync::Arc};
+use tokio::sync::{RwLock, watch};
+
+/// Общее состояние маршрутов, каждое поле извлекается отдельным `State<T>`
+#[derive(Clone)]
+struct AppState {
+    handler: Arc<dyn MessageHandler>,
+    settings: Arc<RwLock<Settings>>,
+    session: Arc<Session>,
+    history: HistoryDir,
+}
+
+/// Каталог конфига, где лежит история для `/latest` и `/history`
+#[derive(Clone)]
+struct HistoryDir(Arc<str>);
+
+impl FromRef<AppState> for Arc<dyn MessageHandler> {
+    fn from_ref(state: &AppState) -> Self {
+        state.handler.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<RwLock<Settings>> {
+    fn from_ref(state: &AppState) -> Self {
+        state.settings.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<Session> {
+    fn from_ref(state: &AppState) -> Self {
+        state.session.clone()
+    }
+}
+
+impl FromRef<AppState> for HistoryDir {
+    fn from_ref(state: &AppState) -> Self {
+        state.history.clone()
+    }
+}
+
+#[derive(Deserialize)]
+struct Since {
+    /// записи с id больше этого
+    #[serde(default)]
+    since: u64,
+}
 
 pub struct ServerAXUM {
     host: String,
-    fnt: fn(&str, &str, &Settings, &str) -> Result<()>,
+    handler: Arc<dyn MessageHandler>,
     port: u16,
-    settings: Settings,
-    config_dir: String,
+    settings: Arc<RwLock<Settings>>,
+    session: Arc<Session>,
+    listener: Option<std::net::TcpListener>,
+    shutdown: Option<watch::Receiver<bool>>,
+    metrics: bool,
+    history: Option<String>,
+    limits: Arc<Limits>,
 }
 
 impl ServerAXUM {
     pub fn new(
         host: &str,
         port: u16,
-        fnt: fn(&str, &str, &Settings, &str) -> Result<()>,
+        handler: Arc<dyn MessageHandler>,
         settings: Settings,
-        config_dir: &str,
     ) -> Self {
         Self {
             host: host.to_string(),
-            fnt,
+            handler,
             port,
-            settings,
-            config_dir: config_dir.to_string(),
+            limits: Arc::new(Limits::new(settings.limits.clone())),
+            settings: Arc::new(RwLock::new(settings)),
+            session: Arc::new(Session::new()),
+            listener: None,
+            shutdown: None,
+            metrics: false,
+            history: None,
         }
     }
 
+    /// Уже открытый сокет, например переданный systemd (socket activation)
+    pub fn with_listener(mut self, listener: std::net::TcpListener) -> Self {
+        self.listener = Some(listener);
+        self
+    }
+
+    /// Сервер завершается, когда в канал приходит `true`, дожидаясь текущих запросов
+    pub fn with_shutdown(mut self, shutdown: watch::Receiver<bool>) -> Self {
+        self.shutdown = Some(shutdown);
+        self
+    }
+
+    /// Общая с обработчиками сообщений сессия вместо своей
+    pub fn with_session(mut self, session: Arc<Session>) -> Self {
+        self.session = session;
+        self
+    }
+
+    /// Включает `/metrics` для Prometheus
+    pub fn with_metrics(mut self) -> Self {
+        self.metrics = true;
+        self
+    }
+
+    /// Включает `/latest` и `/history` по истории из `config_dir`
+    pub fn with_history(mut self, config_dir: &str) -> Self {
+        self.history = Some(config_dir.to_string());
+        self
+    }
+
+    /// Настройки, которые можно заменить на ходу (SIGHUP)
+    pub fn settings(&self) -> Arc<RwLock<Settings>> {
+        self.settings.clone()
+    }
+
+    /// Хэши принятых конвертов и счётчики, общие с управляющим сокетом
+    pub fn session(&self) -> Arc<Session> {
+        self.session.clone()
+    }
+
     fn router(&self) -> Router {
-        // Use a single tuple state containing both the function pointer and the Settings.
-        // This avoids requiring FromRef implementations for extracting multiple separate State<T>
-        // values and keeps the handler signature simple.
-        let app_state: (
-            fn(&str, &str, &Settings, &str) -> Result<()>,
-            Settings,
-            Arc<RwLock<HashSet<[u8; 28]>>>,
-            String,
-        ) = (
-            self.fnt,
-            self.settings.clone(),
-            Arc::new(RwLock::new(HashSet::with_capacity(312222))),
-            self.config_dir.clone(),
-        );
+        let app_state = AppState {
+            handler: self.handler.clone(),
+            settings: self.settings.clone(),
+            session: self.session.clone(),
+            history: HistoryDir(self.history.as_deref().unwrap_or_default().into()),
+        };
 
-        Router::new()
+        let mut routes = Router::new()
             .route("/text", post(Self::text))
-            .route("/", get("Bufsy"))
-            .with_state(app_state)
+            .route("/status", post(Self::status));
+        if self.history.is_some() {
+            routes = routes
+                .route("/latest", get(Self::latest))
+                .route("/history", get(Self::history));
+        }
+        // слои действуют на маршруты выше них, последний добавленный — внешний
+        let mut router = routes
+            .route_layer(middleware::from_fn_with_state(
+                self.limits.clone(),
+                limits::decrypt_limit,
+            ))
+            .route_layer(middleware::from_fn_with_state(
+                self.limits.clone(),
+                limits::rate_limit,
+            ))
+            .route_layer(DefaultBodyLimit::max(self.limits.max_body()))
+            .route("/", get("Bufsy"));
+        if self.metrics {
+            router = router.route("/metrics", get(Self::metrics));
+        }
+        router.with_state(app_state)
     }
 
-    // The `text` handler now extracts the whole application state tuple `(fn, Settings)`
-    // as a single `State` value and destructures it locally.
     async fn text(
         ConnectInfo(addr): ConnectInfo<SocketAddr>,
-        State((fnt_handler, settings, db, config_dir)): State<(
-            fn(&str, &str, &Settings, &str) -> Result<()>,
-            Settings,
-            Arc<RwLock<HashSet<[u8; 28]>>>,
-            String,
-        )>,
-        body: String,
-    ) -> String {
-        let mut hasher = Sha224::new();
-        hasher.update(body.as_bytes());
-        let result: [u8; 28] = hasher.finalize().as_slice().try_into().unwrap();
-        let mut db_rw = db.write().await;
-        if !db_rw.contains(&result) {
-            db_rw.insert(result);
-            fnt_handler(&body, &addr.ip().to_string(), &settings, &config_dir).unwrap();
-            "oK".to_string()
-        } else {
-            "Error".to_string()
+        State(handler): State<Arc<dyn MessageHandler>>,
+        State(settings): State<Arc<RwLock<Settings>>>,
+        State(session): State<Arc<Session>>,
+        body: Result<String, StringRejection>,
+    ) -> Result<String, ApiError> {
+        let metrics = &session.metrics;
+        Metrics::inc(&metrics.received, 1);
+        let body = body.inspect_err(|_| Metrics::inc(&metrics.rejected, 1))?;
+        Metrics::inc(&metrics.bytes_in, body.len() as u64);
+        let peer = peer_host(&addr);
+        let rejected = |code: ErrorCode, message: String| {
+            Metrics::inc(
+                if code == ErrorCode::DecryptFailed {
+                    &metrics.decrypt_failed
+                } else {
+                    &metrics.rejected
+                },
+                1,
+            );
+            tracing::debug!(%peer, ?code, error = message, "Message rejected");
+            ApiError::new(code, message)
+        };
+
+        let envelope =
+            Envelope::parse(&body).map_err(|e| rejected(ErrorCode::Malformed, e.to_string()))?;
+        let settings = settings.read().await.clone();
+        match envelope.kind {
+            Kind::Ping => {
+                return pong(&envelope, &settings, &session)
+                    .await
+                    .map_err(|e| rejected(e.error, e.message));
+            }
+            Kind::Text => {}
+            // перехваченный announce нельзя прислать позже, чтобы увести адрес устройства
+            Kind::Announce if !envelope.is_fresh() => {
+                return Err(rejected(
+                    ErrorCode::Expired,
+                    "Announce is too old".to_string(),
+                ));
+            }
+            Kind::Announce => {}
+            kind => {
+                return Err(rejected(
+                    ErrorCode::Malformed,
+                    format!("Unexpected {} message", kind.as_str()),
+                ));
+            }
         }
+        if !session.remember(envelope.id()).await {
+            Metrics::inc(&metrics.duplicate, 1);
+            return Err(ApiError::new(ErrorCode::Duplicate, "Already received"));
+        }
+
+        let text = match envelope.open(&settings.key) {
+            Ok(text) => text,
+            Err(_) => {
+                session.forget(envelope.id()).await;
+                return Err(rejected(
+                    ErrorCode::DecryptFailed,
+                    "Key does not match".to_string(),
+                ));
+            }
+        };
+        tracing::debug!(
+            %peer,
+            kind = envelope.kind.as_str(),
+            bytes = text.len(),
+            "Received"
+        );
+        let message = Message {
+            envelope,
+            text,
+            peer: peer.clone(),
+        };
+        if let Err(e) = handler.handle(&message).await {
+            // повтор после временной ошибки должен дойти, а не получить `duplicate`; история
+            // и пересылка стоят в цепочке после буфера обмена, так что ещё не сработали
+            session.forget(message.envelope.id()).await;
+            tracing::warn!(%peer, error = %e, "Failed to handle message");
+            return Err(rejected(
+                ErrorCode::Internal,
+                "Failed to handle message".to_string(),
+            ));
+        }
+        Metrics::inc(&metrics.accepted, 1);
+        Ok("oK".to_string())
     }
 
-    pub fn address(&self) -> SocketAddr {
-        SocketAddr::new(
-            self.host.parse().unwrap_or_else(|_| {
-                // If parsing as an `IpAddr` fails, default to the IPv4 loopback address (127.0.0.1).
-                // This resolves the panic for "localhost" and provides a fallback for other
-                // hostnames that cannot be directly parsed as IP addresses.
-                std::net::IpAddr::V4(std::net::Ipv4Addr::LOCALHOST)
-            }),
-            self.port,
+    /// Состояние устройства, только для того, кто знает ключ; ответ тоже зашифрован
+    async fn status(
+        State(settings): State<Arc<RwLock<Settings>>>,
+        State(session): State<Arc<Session>>,
+        body: Result<String, StringRejection>,
+    ) -> Result<String, ApiError> {
+        let body = body?;
+        let settings = settings.read().await.clone();
+        verify(
+            Envelope::parse(&body).ok(),
+            Kind::Status,
+            "/status",
+            &settings,
+            &session,
         )
+        .await?;
+
+        let status = NodeStatus {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            device: gethostname::gethostname().to_string_lossy().to_string(),
+            device_id: settings.device_id.clone(),
+            uptime: now().saturating_sub(session.started),
+            fingerprint: fingerprint(&settings.key),
+        };
+        seal_reply(Kind::Status, &status, &settings)
+    }
+
+    /// Последняя запись истории, кроме секретов; запрос и ответ зашифрованы, как у `/status`
+    async fn latest(
+        State(settings): State<Arc<RwLock<Settings>>>,
+        State(session): State<Arc<Session>>,
+        State(HistoryDir(dir)): State<HistoryDir>,
+        headers: HeaderMap,
+    ) -> Result<String, ApiError> {
+        let settings = settings.read().await.clone();
+        authorize(&headers, "/latest", &settings, &session).await?;
+        let item = shared(&dir, &settings)?
+            .pop()
+            .ok_or_else(|| ApiError::new(ErrorCode::NotFound, "History is empty"))?;
+        seal_reply(Kind::Pull, &item, &settings)
+    }
+
+    /// Записи после `since`, от старых к новым
+    async fn history(
+        State(settings): State<Arc<RwLock<Settings>>>,
+        State(session): State<Arc<Session>>,
+        State(HistoryDir(dir)): State<HistoryDir>,
+        Query(Since { since }): Query<Since>,
+        headers: HeaderMap,
+    ) -> Result<String, ApiError> {
+        let settings = settings.read().await.clone();
+        let route = format!("/history?since={}", since);
+        authorize(&headers, &route, &settings, &session).await?;
+        let mut items = shared(&dir, &settings)?;
+        items.retain(|item| item.id > since);
+        seal_reply(Kind::Pull, &items, &settings)
+    }
+
+    async fn metrics(
+        State(session): State<Arc<Session>>,
+    ) -> ([(header::HeaderName, &'static str); 1], String) {
+        (
+            [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+            session.metrics.render(session.seen().await),
+        )
+    }
+
+    /// Адрес для `bind`: IP как есть, имя хоста — через DNS
+    pub async fn address(&self) -> Result<SocketAddr> {
+        Ok(Address::new(&self.host, self.port).resolve().await?[0])
     }
 
     pub async fn run(&mut self) -> Result<()> {
         let app = self.router();
 
-        let listener = tokio::net::TcpListener::bind(self.address()).await?;
+        let listener = match self.listener.take() {
+            Some(listener) => {
+                listener.set_nonblocking(true)?;
+                tokio::net::TcpListener::from_std(listener)?
+            }
+            None => {
+                let interface = self.settings.read().await.network.interface.clone();
+                bind(self.address().await?, interface.as_deref())?
+            }
+        };
+        let mut shutdown = self.shutdown.take();
         axum::serve(
             listener,
             app.into_make_service_with_connect_info::<SocketAddr>(),
         )
+        .with_graceful_shutdown(async move {
+            match shutdown.as_mut() {
+                Some(shutdown) => {
+                    let _ = shutdown.wait_for(|stop| *stop).await;
+                }
+                None => std::future::pending().await,
+            }
+        })
         .await?;
         Ok(())
     }
 }
 
+/// `Authorization: Bufsy <конверт Pull>`: одноразовый свежий токен для пути `route`
+async fn authorize(
+    headers: &HeaderMap,
+    route: &str,
+    settings: &Settings,
+    session: &Session,
+) -> Result<(), ApiError> {
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bufsy "))
+        .and_then(|token| Envelope::parse(token).ok());
+    verify(token, Kind::Pull, route, settings, session).await
+}
+
+/// Запрос к маршруту `route`: подписанный тип `kind` и имя маршрута внутри (ответ
+/// с JSON за запрос не сойдёт), отправлен недавно и раньше не приходил
+async fn verify(
+    envelope: Option<Envelope>,
+    kind: Kind,
+    route: &str,
+    settings: &Settings,
+    session: &Session,
+) -> Result<(), ApiError> {
+    let Some(envelope) = envelope.filter(|envelope| {
+        envelope.kind == kind && envelope.open(&settings.key).is_ok_and(|text| text == route)
+    }) else {
+        return Err(ApiError::new(
+            ErrorCode::DecryptFailed,
+            "Key does not match",
+        ));
+    };
+    if !envelope.is_fresh() {
+        return Err(ApiError::new(ErrorCode::Expired, "Request is too old"));
+    }
+    if !session.remember(envelope.id()).await {
+        return Err(ApiError::new(ErrorCode::Duplicate, "Already received"));
+    }
+    Ok(())
+}
+
+/// Подробности внутренней ошибки остаются в логе, устройству уходит только код
+fn internal(error: impl std::fmt::Display) -> ApiError {
+    tracing::warn!(%error, "Internal error");
+    ApiError::new(ErrorCode::Internal, "Internal error")
+}
+
+/// История, которую можно отдать другим устройствам: без секретов
+fn shared(config_dir: &str, settings: &Settings) -> Result<Vec<Item>, ApiError> {
+    let mut items = History::open(config_dir, settings)
+        .list()
+        .map_err(internal)?;
+    items.retain(|item| !item.sensitive);
+    Ok(items)
+}
+
+/// JSON, зашифрованный общим ключом, в конверте `kind`
+fn seal_reply<T: Serialize>(
+    kind: Kind,
+    value: &T,
+    settings: &Settings,
+) -> Result<String, ApiError> {
+    let sealed = serde_json::to_string(value)
+        .map_err(anyhow::Error::from)
+        .and_then(|json| {
+            Envelope {
+                kind,
+                ..Envelope::new(settings.server.port, &settings.device_id)
+            }
+            .seal(&json, &settings.key)
+        });
+    match sealed {
+        Ok(envelope) => Ok(envelope.to_string()),
+        Err(e) => Err(internal(e)),
+    }
+}
+
+/// Ответ на свежий ping: HMAC его текста, а не сам текст, — так pong не превращается
+/// в чужое сообщение. Буфер обмена и outbox не трогаем.
+async fn pong(ping: &Envelope, settings: &Settings, session: &Session) -> Result<String, ApiError> {
+    if !ping.is_fresh() {
+        return Err(ApiError::new(ErrorCode::Expired, "Ping is too old"));
+    }
+    let challenge = ping
+        .open(&settings.key)
+        .map_err(|_| ApiError::new(ErrorCode::DecryptFailed, "Key does not match"))?;
+    if !session.remember(ping.id()).await {
+        return Err(ApiError::new(ErrorCode::Duplicate, "Already received"));
+    }
+    mac(&settings.key, &challenge)
+        .map_err(anyhow::Error::msg)
+        .and_then(|answer| {
+            Envelope {
+                kind: Kind::Pong,
+                ..Envelope::new(settings.server.port, &settings.device_id)
+            }
+            .seal(&answer, &settings.key)
+        })
+        .map(|pong| pong.to_string())
+        .map_err(internal)
+}
+
+/// `::` слушает и IPv6, и IPv4 (dual-stack), остальные адреса — как есть.
+/// С `interface` сокет привязан к интерфейсу и переживает смену его адресов.
+fn bind(address: SocketAddr, interface: Option<&str>) -> Result<tokio::net::TcpListener> {
+    let socket = Socket::new(Domain::for_address(address), Type::STREAM, None)?;
+    if address.is_ipv6() {
+        socket.set_only_v6(!address.ip().is_unspecified())?;
+    }
+    if let Some(interface) = interface {
+        socket
+            .bind_device(Some(interface.as_bytes()))
+            .map_err(|e| anyhow::anyhow!("Failed to bind to interface {}: {}", interface, e))?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&address.into())?;
+    socket.listen(1024)?;
+    Ok(tokio::net::TcpListener::from_std(socket.into())?)
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::config::load_config::tests::{test_config_dir, test_load_config};
-    use std::net::{IpAddr, Ipv4Addr};
+    use crate::app::handler::BoxFuture;
+    use crate::config::load_config::tests::test_load_config;
 
     use axum::{
         body::Body,
@@ -122,13 +511,7 @@ mod tests {
 
     #[tokio::test]
     async fn new_router() {
-        let server = ServerAXUM::new(
-            "localhost",
-            8099,
-            fnt_test,
-            test_load_config(),
-            &test_config_dir(),
-        );
+        let server = ServerAXUM::new("localhost", 8099, Arc::new(Accept), test_load_config());
         let app = server.router();
 
         let response = app
@@ -142,126 +525,388 @@ mod tests {
         assert_eq!(&body[..], b"Bufsy");
     }
 
-    #[test]
-    fn address_new() {
-        let server = ServerAXUM::new(
-            "111.168.11.75",
-            8084,
-            fnt_test,
-            test_load_config(),
-            &test_config_dir(),
-        );
+    #[tokio::test]
+    async fn address_new() {
+        let server = ServerAXUM::new("111.168.11.75", 8084, Arc::new(Accept), test_load_config());
         assert_eq!(
-            server.address().to_string(),
+            server.address().await.unwrap().to_string(),
             "111.168.11.75:8084".to_string()
         );
 
+        let server = ServerAXUM::new("localhost", 999, Arc::new(Accept), test_load_config());
+        let address = server.address().await.unwrap();
+        assert!(address.ip().is_loopback());
+        assert_eq!(address.port(), 999);
+
+        let server = ServerAXUM::new("::", 9296, Arc::new(Accept), test_load_config());
+        assert_eq!(server.address().await.unwrap().to_string(), "[::]:9296");
+
         let server = ServerAXUM::new(
-            "localhost",
+            "nosuchhost.invalid",
             999,
-            fnt_test,
+            Arc::new(Accept),
             test_load_config(),
-            &test_config_dir(),
         );
-        assert_eq!(server.address().to_string(), "127.0.0.1:999".to_string());
+        assert!(server.address().await.is_err());
     }
 
     #[tokio::test]
-    async fn text_echo() {
-        let server = ServerAXUM::new(
+    async fn bind_dual_stack() {
+        let listener = bind("[::]:0".parse().unwrap(), None).unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        tokio::net::TcpStream::connect(("127.0.0.1", port))
+            .await
+            .unwrap();
+        tokio::net::TcpStream::connect(("::1", port)).await.unwrap();
+        let (_, from) = listener.accept().await.unwrap();
+        assert_eq!(peer_host(&from), "127.0.0.1");
+    }
+
+    #[tokio::test]
+    async fn graceful_shutdown() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let (stop, shutdown) = watch::channel(false);
+        let mut server = ServerAXUM::new(
             "localhost",
-            8080,
-            fnt_test,
+            address.port(),
+            Arc::new(Accept),
             test_load_config(),
-            &test_config_dir(),
-        );
-        let result = ServerAXUM::text(
-            ConnectInfo(SocketAddr::new(
-                IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
-                8080,
-            )),
-            State((
-                server.fnt,
-                server.settings,
-                Arc::new(RwLock::new(HashSet::new())),
-                test_config_dir(),
-            )),
-            "catRUST_***_rust w :>".to_string(),
         )
-        .await;
+        .with_listener(listener)
+        .with_shutdown(shutdown);
+        let running = tokio::spawn(async move { server.run().await });
 
-        assert_eq!(result, "oK".to_string());
+        let body = reqwest::get(format!("http://{}/", address))
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert_eq!(body, "Bufsy");
+
+        stop.send(true).unwrap();
+        tokio::time::timeout(std::time::Duration::from_secs(5), running)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert!(tokio::net::TcpStream::connect(address).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn bind_interface() {
+        let listener = bind("[::]:0".parse().unwrap(), Some("lo")).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::net::TcpStream::connect(("127.0.0.1", port))
+            .await
+            .unwrap();
+
+        assert!(bind("[::]:0".parse().unwrap(), Some("nosuchif0")).is_err());
+    }
+
+    /// Отправляет `body` на `/text` от имени `peer`, как это делает axum
+    async fn post_text(server: &ServerAXUM, peer: [u8; 4], body: &str) -> Result<String, ApiError> {
+        ServerAXUM::text(
+            ConnectInfo(SocketAddr::from((peer, 8080))),
+            State(server.handler.clone()),
+            State(server.settings()),
+            State(server.session()),
+            Ok(body.to_string()),
+        )
+        .await
+    }
+
+    fn sealed(text: &str) -> String {
+        let config = test_load_config();
+        Envelope::new(config.server.port, &config.device_id)
+            .seal(text, &config.key)
+            .unwrap()
+            .to_string()
+    }
+
+    #[tokio::test]
+    async fn text_echo() {
+        let received = Arc::new(Received::default());
+        let server = ServerAXUM::new("localhost", 8080, received.clone(), test_load_config());
+        let result = post_text(&server, [127, 0, 0, 1], &sealed("catRUST_***_rust w :>")).await;
+
+        assert_eq!(result.unwrap(), "oK");
+        let messages = received.0.lock().unwrap();
+        assert_eq!(messages[0].text, "catRUST_***_rust w :>");
+        assert_eq!(messages[0].peer, "127.0.0.1");
     }
 
     #[tokio::test]
     async fn text_error_hash() {
-        let server = ServerAXUM::new(
-            "localhost",
-            8080,
-            fnt_test,
-            test_load_config(),
-            &test_config_dir(),
+        let server = ServerAXUM::new("localhost", 8080, Arc::new(Accept), test_load_config());
+        let first = sealed("catRUST_***_rust w :>");
+        assert_eq!(
+            post_text(&server, [127, 0, 0, 3], &first).await.unwrap(),
+            "oK"
         );
-        let db: Arc<RwLock<HashSet<[u8; 28]>>> = Arc::new(RwLock::new(HashSet::new()));
-        let result = ServerAXUM::text(
-            ConnectInfo(SocketAddr::new(
-                IpAddr::V4(Ipv4Addr::new(127, 0, 0, 3)),
-                8080,
-            )),
-            State((
-                server.fnt,
-                server.settings.clone(),
-                db.clone(),
-                test_config_dir(),
-            )),
-            "catRUST_***_rust w :>".to_string(),
-        )
-        .await;
-        assert_eq!(result, "oK".to_string());
-        let result = ServerAXUM::text(
-            ConnectInfo(SocketAddr::new(
-                IpAddr::V4(Ipv4Addr::new(127, 1, 0, 2)),
-                8080,
-            )),
-            State((
-                server.fnt,
-                server.settings.clone(),
-                db.clone(),
-                test_config_dir(),
-            )),
-            "ca5R :>".to_string(),
-        )
-        .await;
-        assert_eq!(result, "oK".to_string());
-        let result = ServerAXUM::text(
-            ConnectInfo(SocketAddr::new(
-                IpAddr::V4(Ipv4Addr::new(127, 1, 0, 24)),
-                8081,
-            )),
-            State((
-                server.fnt,
-                server.settings.clone(),
-                db.clone(),
-                test_config_dir(),
-            )),
-            "catR :>".to_string(),
-        )
-        .await;
-        assert_eq!(result, "oK".to_string());
-        let result = ServerAXUM::text(
-            ConnectInfo(SocketAddr::new(
-                IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2)),
-                8080,
-            )),
-            State((server.fnt, server.settings, db, test_config_dir())),
-            "catRUST_***_rust w :>".to_string(),
-        )
-        .await;
+        assert_eq!(
+            post_text(&server, [127, 1, 0, 2], &sealed("ca5R :>"))
+                .await
+                .unwrap(),
+            "oK"
+        );
+        assert_eq!(
+            post_text(&server, [127, 1, 0, 24], &sealed("catR :>"))
+                .await
+                .unwrap(),
+            "oK"
+        );
+        let result = post_text(&server, [127, 0, 0, 2], &first).await;
+        assert_eq!(result.unwrap_err().error, ErrorCode::Duplicate);
+    }
+
+    #[tokio::test]
+    async fn text_error_codes() {
+        let server = ServerAXUM::new("localhost", 8080, Arc::new(Reject), test_load_config());
+        for body in [
+            "test|test|2|:1",
+            "cat :>|",
+            "|",
+            "|cat :>||||||||cat||||:)||",
+        ] {
+            let result = post_text(&server, [127, 0, 0, 1], body).await;
+            assert_eq!(result.unwrap_err().error, ErrorCode::Malformed);
+        }
 
-        assert_eq!(result, "Error".to_string());
+        let foreign = Envelope::new(9296, "d1e5c0de")
+            .seal("cat", &"07".repeat(32))
+            .unwrap();
+        let result = post_text(&server, [127, 0, 0, 1], &foreign.to_string()).await;
+        assert_eq!(result.unwrap_err().error, ErrorCode::DecryptFailed);
+
+        let body = sealed("cat");
+        let error = post_text(&server, [127, 0, 0, 1], &body).await.unwrap_err();
+        assert_eq!(error.error, ErrorCode::Internal);
+        assert_eq!(error.message, "Failed to handle message");
+        // после ошибки обработчика повтор не считается дубликатом
+        let result = post_text(&server, [127, 0, 0, 1], &body).await;
+        assert_eq!(result.unwrap_err().error, ErrorCode::Internal);
+
+        let config = test_load_config();
+        let stale = Envelope {
+            kind: Kind::Announce,
+            sent: Some(now() - 3600),
+            ..Envelope::new(config.server.port, &config.device_id)
+        }
+        .seal(&config.device_id, &config.key)
+        .unwrap();
+        let result = post_text(&server, [127, 0, 0, 1], &stale.to_string()).await;
+        assert_eq!(result.unwrap_err().error, ErrorCode::Expired);
     }
 
-    fn fnt_test(_text: &str, _addr: &str, _settings: &Settings, _config_dir: &str) -> Result<()> {
-        Ok(())
+    #[tokio::test]
+    async fn status_requests() {
+        let server = ServerAXUM::new("localhost", 8080, Arc::new(Accept), test_load_config());
+        let config = test_load_config();
+        let status = |body: String| {
+            ServerAXUM::status(State(server.settings()), State(server.session()), Ok(body))
+        };
+        let request = |sent: u64| {
+            Envelope {
+                kind: Kind::Status,
+                sent: Some(sent),
+                ..Envelope::new(config.server.port, &config.device_id)
+            }
+            .seal("/status", &config.key)
+            .unwrap()
+            .to_string()
+        };
+
+        let body = request(now());
+        let reply = status(body.clone()).await.unwrap();
+        // повтор запроса и ответ, присланный как запрос, не открывают новый ответ
+        assert_eq!(status(body).await.unwrap_err().error, ErrorCode::Duplicate);
+        assert_eq!(
+            status(reply).await.unwrap_err().error,
+            ErrorCode::DecryptFailed
+        );
+        assert_eq!(
+            status(request(now() - 3600)).await.unwrap_err().error,
+            ErrorCode::Expired
+        );
+    }
+
+    #[tokio::test]
+    async fn pull_tokens() {
+        let server = ServerAXUM::new("localhost", 8080, Arc::new(Accept), test_load_config());
+        let config = test_load_config();
+        let token = |path: &str| {
+            let token = Envelope {
+                kind: Kind::Pull,
+                ..Envelope::new(config.server.port, &config.device_id)
+            }
+            .seal(path, &config.key)
+            .unwrap();
+            let mut headers = HeaderMap::new();
+            headers.insert(
+                header::AUTHORIZATION,
+                format!("Bufsy {}", token).parse().unwrap(),
+            );
+            headers
+        };
+        let history = |headers: HeaderMap, since: u64| {
+            ServerAXUM::history(
+                State(server.settings()),
+                State(server.session()),
+                State(HistoryDir("test_dir/pull_tokens".into())),
+                Query(Since { since }),
+                headers,
+            )
+        };
+
+        let headers = token("/history?since=0");
+        assert!(history(headers.clone(), 0).await.is_ok());
+        // токен одноразовый и только для своего пути
+        assert_eq!(
+            history(headers, 0).await.unwrap_err().error,
+            ErrorCode::Duplicate
+        );
+        assert_eq!(
+            history(token("/history?since=0"), 5)
+                .await
+                .unwrap_err()
+                .error,
+            ErrorCode::DecryptFailed
+        );
+        assert_eq!(
+            history(token("/latest"), 0).await.unwrap_err().error,
+            ErrorCode::DecryptFailed
+        );
+    }
+
+    #[tokio::test]
+    async fn ping_once() {
+        let received = Arc::new(Received::default());
+        let server = ServerAXUM::new("localhost", 8080, received.clone(), test_load_config());
+        let config = test_load_config();
+        let ping = Envelope {
+            kind: Kind::Ping,
+            ..Envelope::new(config.server.port, &config.device_id)
+        }
+        .seal("cat", &config.key)
+        .unwrap()
+        .to_string();
+
+        let pong = post_text(&server, [127, 0, 0, 1], &ping).await.unwrap();
+        let pong = Envelope::parse(&pong).unwrap();
+        assert_eq!(pong.kind, Kind::Pong);
+        assert_eq!(
+            pong.open(&config.key).unwrap(),
+            mac(&config.key, "cat").unwrap()
+        );
+        let result = post_text(&server, [127, 0, 0, 1], &ping).await;
+        assert_eq!(result.unwrap_err().error, ErrorCode::Duplicate);
+        assert!(received.0.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn metrics_route() {
+        let server = ServerAXUM::new("localhost", 8080, Arc::new(Accept), test_load_config());
+        let response = server
+            .router()
+            .oneshot(
+                Request::builder()
+                    .uri("/metrics")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let server = server.with_metrics();
+        let body = sealed("cat :>");
+        for _ in 0..2 {
+            server
+                .router()
+                .oneshot(
+                    Request::post("/text")
+                        .extension(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 8080))))
+                        .body(Body::from(body.clone()))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+        }
+        let response = server
+            .router()
+            .oneshot(
+                Request::builder()
+                    .uri("/metrics")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let text = String::from_utf8_lossy(&body);
+        assert!(text.contains("bufsy_messages_received_total 2\n"));
+        assert!(text.contains("bufsy_messages_duplicate_total 1\n"));
+        assert!(text.contains("bufsy_dedup_cache_size 1\n"));
+    }
+
+    #[tokio::test]
+    async fn text_limits() {
+        let mut settings = test_load_config();
+        settings.limits.max_body = 256;
+        settings.limits.rate = 1;
+        settings.limits.burst = 2;
+        let server = ServerAXUM::new("localhost", 8080, Arc::new(Accept), settings);
+        let post = |body: &str| {
+            server.router().oneshot(
+                Request::post("/text")
+                    .extension(ConnectInfo(SocketAddr::from(([10, 0, 0, 9], 8080))))
+                    .body(Body::from(body.to_string()))
+                    .unwrap(),
+            )
+        };
+
+        let response = post(&"x".repeat(512)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(
+            post(&sealed("small")).await.unwrap().status(),
+            StatusCode::OK
+        );
+        let response = post("again").await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    /// Принимает всё
+    struct Accept;
+
+    impl MessageHandler for Accept {
+        fn handle<'a>(&'a self, _message: &'a Message) -> BoxFuture<'a, Result<()>> {
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    /// Отклоняет всё
+    struct Reject;
+
+    impl MessageHandler for Reject {
+        fn handle<'a>(&'a self, _message: &'a Message) -> BoxFuture<'a, Result<()>> {
+            Box::pin(async { Err(anyhow::anyhow!("Rejected")) })
+        }
+    }
+
+    /// Складывает принятые сообщения
+    #[derive(Default)]
+    struct Received(std::sync::Mutex<Vec<Message>>);
+
+    impl MessageHandler for Received {
+        fn handle<'a>(&'a self, message: &'a Message) -> BoxFuture<'a, Result<()>> {
+            Box::pin(async move {
+                self.0.lock().unwrap().push(message.clone());
+                Ok(())
+            })
+        }
     }
 }
@@ -0,0 +1,286 @@
+use super::{
+    address::{Address, Resolver},
+    error::{ApiError, ErrorCode, HttpError},
+};
+use crate::{
+    app::{
+        encryption::{generate_nonce, mac},
+        envelope::{Envelope, Kind},
+    },
+    config::{Settings, settings::ClientSettings},
+};
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::task::JoinSet;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryStatus {
+    Delivered,
+    /// у получателя это сообщение уже есть
+    Duplicate,
+    /// получатель отказался, повтор не поможет (другой ключ, битый или слишком большой конверт)
+    Rejected,
+    Failed,
+    Timeout,
+}
+
+impl DeliveryStatus {
+    pub fn is_delivered(self) -> bool {
+        matches!(self, DeliveryStatus::Delivered | DeliveryStatus::Duplicate)
+    }
+
+    /// Стоит ли класть сообщение в outbox
+    pub fn is_retryable(self) -> bool {
+        matches!(self, DeliveryStatus::Failed | DeliveryStatus::Timeout)
+    }
+
+    /// Итог `deliver`: код ошибки из ответа получателя или ошибка сети
+    pub fn of(result: &Result<()>) -> (Self, Option<ErrorCode>) {
+        let Err(e) = result else {
+            return (DeliveryStatus::Delivered, None);
+        };
+        if let Some(http) = e.downcast_ref::<HttpError>() {
+            let status = match http.code {
+                Some(ErrorCode::Duplicate) => DeliveryStatus::Duplicate,
+                Some(code) if code.is_permanent() => DeliveryStatus::Rejected,
+                _ => DeliveryStatus::Failed,
+            };
+            return (status, http.code);
+        }
+        if e.downcast_ref::<reqwest::Error>()
+            .is_some_and(|e| e.is_timeout())
+        {
+            return (DeliveryStatus::Timeout, None);
+        }
+        (DeliveryStatus::Failed, None)
+    }
+}
+
+/// Результат отправки одному устройству
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Delivery {
+    /// `host:port` получателя
+    pub peer: String,
+    pub status: DeliveryStatus,
+    pub latency_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// код из ответа получателя
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<ErrorCode>,
+    /// сообщение оставлено в outbox для повторной отправки
+    pub queued: bool,
+}
+
+/// Клиент с таймаутами из конфига. Ошибка, а не клиент по умолчанию: тот ходил бы
+/// без таймаутов и мимо своего resolver
+pub fn build(settings: &ClientSettings) -> Result<reqwest::Client> {
+    Ok(reqwest::Client::builder()
+        .connect_timeout(Duration::from_millis(settings.connect_timeout_ms))
+        .timeout(Duration::from_millis(settings.timeout_ms))
+        .dns_resolver(Arc::new(Resolver))
+        .build()?)
+}
+
+/// Отправляет готовый конверт на `/text` устройства `address` (`host:port`, `[v6]:port`)
+pub async fn deliver(client: &reqwest::Client, address: &str, body: &str) -> Result<()> {
+    let resp = client
+        .post(address.parse::<Address>()?.url("/text"))
+        .body(body.to_string())
+        .send()
+        .await?;
+    if !resp.status().is_success() {
+        return Err(http_error(address, resp).await.into());
+    }
+    Ok(())
+}
+
+/// Разбирает JSON-ошибку получателя; у старых версий тела нет, остаётся только статус
+pub(super) async fn http_error(address: &str, resp: reqwest::Response) -> HttpError {
+    let status = resp.status().as_u16();
+    let body = resp.text().await.unwrap_or_default();
+    let (code, message) = match serde_json::from_str::<ApiError>(&body) {
+        Ok(error) => (Some(error.error), error.message),
+        Err(_) => (None, String::new()),
+    };
+    HttpError {
+        peer: address.to_string(),
+        status,
+        code,
+        message,
+    }
+}
+
+/// Ping на `/text`: доказывает, что устройство доступно и ключ совпадает, ничего не копируя.
+/// Возвращает время ответа в миллисекундах.
+pub async fn ping(client: &reqwest::Client, address: &str, config: &Settings) -> Result<u64> {
+    let challenge = hex::encode(generate_nonce());
+    let envelope = Envelope {
+        kind: Kind::Ping,
+        ..Envelope::new(config.server.port, &config.device_id)
+    }
+    .seal(&challenge, &config.key)?;
+    let started = Instant::now();
+    let resp = client
+        .post(address.parse::<Address>()?.url("/text"))
+        .body(envelope.to_string())
+        .send()
+        .await?;
+    if !resp.status().is_success() {
+        let error = http_error(address, resp).await;
+        if error.code == Some(ErrorCode::DecryptFailed) {
+            return Err(anyhow!("{} answered, but the key does not match", address));
+        }
+        return Err(error.into());
+    }
+    let latency = started.elapsed().as_millis() as u64;
+    let pong = Envelope::parse(&resp.text().await?)
+        .ok()
+        .filter(|pong| pong.kind == Kind::Pong)
+        .and_then(|pong| pong.open(&config.key).ok());
+    if pong.is_none() || pong != mac(&config.key, &challenge).ok() {
+        return Err(anyhow!("{} answered, but the key does not match", address));
+    }
+    Ok(latency)
+}
+
+/// Отправляет конверт всем `peers` одновременно, порядок отчёта совпадает с `peers`
+pub async fn fan_out(client: &reqwest::Client, peers: &[String], body: &str) -> Vec<Delivery> {
+    let mut tasks = JoinSet::new();
+    for (index, peer) in peers.iter().enumerate() {
+        let client = client.clone();
+        let peer = peer.clone();
+        let body = body.to_string();
+        tasks.spawn(async move {
+            let started = Instant::now();
+            let result = deliver(&client, &peer, &body).await;
+            let (status, code) = DeliveryStatus::of(&result);
+            (
+                index,
+                Delivery {
+                    peer,
+                    status,
+                    latency_ms: started.elapsed().as_millis() as u64,
+                    error: result.err().map(|e| e.to_string()),
+                    code,
+                    queued: false,
+                },
+            )
+        });
+    }
+
+    let mut report: Vec<(usize, Delivery)> = tasks.join_all().await;
+    report.sort_by_key(|(index, _)| *index);
+    report.into_iter().map(|(_, delivery)| delivery).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn deliver_ipv6() {
+        let listener = tokio::net::TcpListener::bind("[::1]:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let app = axum::Router::new().route("/text", axum::routing::post("oK"));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let client = build(&ClientSettings::default()).unwrap();
+        deliver(&client, &address, "aa|bb").await.unwrap();
+        let scoped = address.replace("::1]", "::1%lo]");
+        deliver(&client, &scoped, "aa|bb").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn fan_out_timeout() {
+        // принимает соединения, но никогда не отвечает
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let silent = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let mut sockets = Vec::new();
+            while let Ok((socket, _)) = listener.accept().await {
+                sockets.push(socket);
+            }
+        });
+
+        let client = build(&ClientSettings {
+            connect_timeout_ms: 200,
+            timeout_ms: 300,
+        })
+        .unwrap();
+        let started = Instant::now();
+        let report = fan_out(
+            &client,
+            &[silent.clone(), "127.0.0.1:1".to_string(), silent.clone()],
+            "aa|bb",
+        )
+        .await;
+
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert_eq!(report.len(), 3);
+        assert_eq!(report[0].peer, silent);
+        assert_eq!(report[0].status, DeliveryStatus::Timeout);
+        assert_eq!(report[1].status, DeliveryStatus::Failed);
+        assert_eq!(report[2].status, DeliveryStatus::Timeout);
+    }
+
+    #[tokio::test]
+    async fn fan_out_codes() {
+        use axum::routing::post;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let app = axum::Router::new()
+            .route(
+                "/text",
+                post(|| async { ApiError::new(ErrorCode::DecryptFailed, "Key does not match") }),
+            )
+            .route(
+                "/dup/text",
+                post(|| async { ApiError::new(ErrorCode::Duplicate, "Already received") }),
+            );
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let client = build(&ClientSettings::default()).unwrap();
+        let report = fan_out(&client, &[address.to_string()], "aa|bb").await;
+        assert_eq!(report[0].status, DeliveryStatus::Rejected);
+        assert_eq!(report[0].code, Some(ErrorCode::DecryptFailed));
+        assert!(!report[0].status.is_retryable());
+
+        let result = client
+            .post(format!("http://{}/dup/text", address))
+            .send()
+            .await
+            .unwrap();
+        let result = Err(http_error(&address.to_string(), result).await.into());
+        assert_eq!(DeliveryStatus::of(&result).0, DeliveryStatus::Duplicate);
+    }
+
+    #[tokio::test]
+    async fn ping_pong() {
+        use crate::{
+            app::handler::Chain, config::load_config::tests::test_load_config,
+            transport::axum::ServerAXUM,
+        };
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let mut server =
+            ServerAXUM::new("127.0.0.1", 0, Arc::new(Chain::new()), test_load_config())
+                .with_listener(listener);
+        tokio::spawn(async move { server.run().await });
+
+        let client = build(&ClientSettings::default()).unwrap();
+        let mut config = test_load_config();
+        ping(&client, &address, &config).await.unwrap();
+
+        config.key = "0".repeat(64);
+        let error = ping(&client, &address, &config).await.unwrap_err();
+        assert!(error.to_string().contains("key does not match"));
+    }
+}
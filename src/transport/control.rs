@@ -0,0 +1,175 @@
+use crate::{config::settings::Server, transport::client::Delivery};
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::{fs, io::ErrorKind, os::unix::fs::PermissionsExt};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+};
+
+/// Запрос к работающему `bufsy run`: одна строка JSON на соединение
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+pub enum Request {
+    Send(Outgoing),
+    Status,
+    Peers,
+    /// перечитать конфиг после `bufsy peer`
+    Reload,
+}
+
+/// Что и кому отправить
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct Outgoing {
+    pub text: String,
+    /// адрес не из connections, после доставки он туда добавляется
+    pub ip: Option<String>,
+    /// только эти адреса; без них — все connections
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub to: Vec<String>,
+    /// не сохранять в истории
+    #[serde(default)]
+    pub sensitive: bool,
+}
+
+/// Ответ демона, тоже одна строка JSON
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum Response {
+    Report { deliveries: Vec<Delivery> },
+    Status(DaemonStatus),
+    Peers { peers: Vec<Server> },
+    Reloaded,
+    Error { message: String },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DaemonStatus {
+    pub pid: u32,
+    pub version: String,
+    pub device_id: String,
+    /// адрес, на котором слушает сервер
+    pub listen: String,
+    /// секунды с запуска
+    pub uptime: u64,
+    pub peers: usize,
+    /// сообщений в outbox
+    pub outbox: usize,
+    /// конвертов в кэше повторов
+    pub seen: usize,
+}
+
+/// Сокет лежит рядом с конфигом, у каждого конфига свой демон
+pub fn socket_path(config_dir: &str) -> String {
+    format!("{}/bufsy.sock", config_dir)
+}
+
+/// Открывает управляющий сокет, доступный только владельцу.
+/// Файл от упавшего демона удаляется, живой демон — ошибка.
+pub fn bind(config_dir: &str) -> Result<UnixListener> {
+    let path = socket_path(config_dir);
+    if fs::exists(&path)? {
+        if std::os::unix::net::UnixStream::connect(&path).is_ok() {
+            return Err(anyhow!("bufsy is already running ({})", path));
+        }
+        fs::remove_file(&path)?;
+    }
+    // сокет создаётся сразу с правами 0600: между bind и chmod к нему успели бы подключиться
+    let umask = unsafe { libc::umask(0o177) };
+    let listener = UnixListener::bind(&path);
+    unsafe { libc::umask(umask) };
+    let listener = listener?;
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+/// Принимает запросы, пока задачу не остановят; каждое соединение в своей задаче
+pub async fn serve<F, Fut>(listener: UnixListener, handle: F)
+where
+    F: Fn(Request) -> Fut + Clone + Send + 'static,
+    Fut: Future<Output = Response> + Send,
+{
+    loop {
+        let Ok((stream, _)) = listener.accept().await else {
+            continue;
+        };
+        let handle = handle.clone();
+        tokio::spawn(async move {
+            let (read, mut write) = stream.into_split();
+            let mut line = String::new();
+            if BufReader::new(read).read_line(&mut line).await.is_err() {
+                return;
+            }
+            let response = match serde_json::from_str(&line) {
+                Ok(request) => handle(request).await,
+                Err(e) => Response::Error {
+                    message: format!("Bad request: {}", e),
+                },
+            };
+            if let Ok(mut out) = serde_json::to_string(&response) {
+                out.push('\n');
+                let _ = write.write_all(out.as_bytes()).await;
+            }
+        });
+    }
+}
+
+/// Отправляет запрос демону; `None` — `bufsy run` не запущен
+pub async fn request(config_dir: &str, request: &Request) -> Result<Option<Response>> {
+    let stream = match UnixStream::connect(socket_path(config_dir)).await {
+        Ok(stream) => stream,
+        Err(e) if matches!(e.kind(), ErrorKind::NotFound | ErrorKind::ConnectionRefused) => {
+            return Ok(None);
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let (read, mut write) = stream.into_split();
+    let mut line = serde_json::to_string(request)?;
+    line.push('\n');
+    write.write_all(line.as_bytes()).await?;
+
+    line.clear();
+    BufReader::new(read).read_line(&mut line).await?;
+    match serde_json::from_str(&line)? {
+        Response::Error { message } => Err(anyhow!(message)),
+        response => Ok(Some(response)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn request_roundtrip() {
+        let config_dir = "test_dir/control";
+        fs::create_dir_all(config_dir).unwrap();
+        assert_eq!(request(config_dir, &Request::Peers).await.unwrap(), None);
+
+        let listener = bind(config_dir).unwrap();
+        assert!(bind(config_dir).is_err());
+        let server = tokio::spawn(serve(listener, |request| async move {
+            match request {
+                Request::Peers => Response::Peers {
+                    peers: vec![Server::new("::1", 9296)],
+                },
+                _ => Response::Error {
+                    message: "unsupported".to_string(),
+                },
+            }
+        }));
+
+        assert_eq!(
+            request(config_dir, &Request::Peers).await.unwrap(),
+            Some(Response::Peers {
+                peers: vec![Server::new("::1", 9296)]
+            })
+        );
+        assert!(request(config_dir, &Request::Status).await.is_err());
+
+        // сокет от остановленного демона не мешает следующему запуску
+        server.abort();
+        let _ = server.await;
+        bind(config_dir).unwrap();
+    }
+}
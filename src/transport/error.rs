@@ -0,0 +1,126 @@
+use axum::{
+    Json,
+    extract::rejection::StringRejection,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Код ошибки в JSON-ответе сервера, по нему отправитель решает, повторять ли отправку
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// такой конверт уже получен
+    Duplicate,
+    /// не разбирается как конверт
+    Malformed,
+    /// не расшифровывается ключом получателя
+    DecryptFailed,
+    TooLarge,
+    RateLimited,
+    /// заняты все слоты расшифровки
+    Busy,
+    /// история пуста
+    NotFound,
+    /// служебное сообщение старше `envelope::MAX_AGE`
+    Expired,
+    Internal,
+}
+
+impl ErrorCode {
+    pub fn status(self) -> StatusCode {
+        match self {
+            ErrorCode::Duplicate => StatusCode::CONFLICT,
+            ErrorCode::Malformed => StatusCode::BAD_REQUEST,
+            ErrorCode::DecryptFailed => StatusCode::UNAUTHORIZED,
+            ErrorCode::TooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            ErrorCode::RateLimited => StatusCode::TOO_MANY_REQUESTS,
+            ErrorCode::Busy => StatusCode::SERVICE_UNAVAILABLE,
+            ErrorCode::NotFound => StatusCode::NOT_FOUND,
+            ErrorCode::Expired => StatusCode::UNAUTHORIZED,
+            ErrorCode::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Повтор не поможет: тот же конверт получит тот же ответ
+    pub fn is_permanent(self) -> bool {
+        matches!(
+            self,
+            ErrorCode::Malformed
+                | ErrorCode::DecryptFailed
+                | ErrorCode::TooLarge
+                | ErrorCode::Expired
+        )
+    }
+}
+
+/// Тело ответа с ошибкой: `{"error": "decrypt_failed", "message": "..."}`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ApiError {
+    pub error: ErrorCode,
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn new(error: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            error,
+            message: message.into(),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.error.status(), Json(self)).into_response()
+    }
+}
+
+impl From<StringRejection> for ApiError {
+    fn from(rejection: StringRejection) -> Self {
+        let code = if rejection.status() == StatusCode::PAYLOAD_TOO_LARGE {
+            ErrorCode::TooLarge
+        } else {
+            ErrorCode::Malformed
+        };
+        Self::new(code, rejection.body_text())
+    }
+}
+
+/// Неуспешный ответ устройства на стороне отправителя
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpError {
+    pub peer: String,
+    pub status: u16,
+    /// `None` — старая версия без JSON-ошибок
+    pub code: Option<ErrorCode>,
+    pub message: String,
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} answered {}", self.peer, self.status)?;
+        if !self.message.is_empty() {
+            write!(f, ": {}", self.message)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for HttpError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_body() {
+        let error = ApiError::new(ErrorCode::DecryptFailed, "Wrong key");
+        assert_eq!(
+            serde_json::to_string(&error).unwrap(),
+            r#"{"error":"decrypt_failed","message":"Wrong key"}"#
+        );
+        assert_eq!(error.into_response().status(), StatusCode::UNAUTHORIZED);
+    }
+}
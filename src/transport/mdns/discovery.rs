@@ -0,0 +1,154 @@
+use anyhow::Result;
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+use std::{net::IpAddr, time::Duration};
+
+/// Тип сервиса, под которым bufsy объявляет себя в локальной сети
+pub const SERVICE_TYPE: &str = "_bufsy._tcp.local.";
+
+/// Найденное в сети устройство bufsy
+#[derive(Debug, Clone, PartialEq)]
+pub struct Peer {
+    pub name: String,
+    pub host: String,
+    pub port: u16,
+    pub fingerprint: String,
+    pub id: Option<String>,
+}
+
+/// Объявление и поиск устройств через mDNS/DNS-SD
+pub struct Discovery {
+    daemon: ServiceDaemon,
+    name: String,
+}
+
+impl Discovery {
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            daemon: ServiceDaemon::new()?,
+            name: gethostname::gethostname().to_string_lossy().to_string(),
+        })
+    }
+
+    /// Объявляет `_bufsy._tcp` с портом, отпечатком ключа и id устройства
+    pub fn advertise(&self, port: u16, fingerprint: &str, device_id: &str) -> Result<()> {
+        let info = ServiceInfo::new(
+            SERVICE_TYPE,
+            &self.name,
+            &format!("{}.local.", self.name),
+            "",
+            port,
+            &[("fp", fingerprint), ("id", device_id)][..],
+        )?
+        .enable_addr_auto();
+        self.daemon.register(info)?;
+        Ok(())
+    }
+
+    /// Ищет устройства с тем же отпечатком ключа в течение `timeout`
+    pub async fn browse(&self, timeout: Duration, fingerprint: &str) -> Result<Vec<Peer>> {
+        let receiver = self.daemon.browse(SERVICE_TYPE)?;
+        let own = format!("{}.{}", self.name, SERVICE_TYPE);
+        let mut peers: Vec<Peer> = Vec::new();
+
+        let deadline = tokio::time::Instant::now() + timeout;
+        while let Ok(Ok(event)) = tokio::time::timeout_at(deadline, receiver.recv_async()).await {
+            let ServiceEvent::ServiceResolved(info) = event else {
+                continue;
+            };
+            if info.get_fullname() == own || info.get_property_val_str("fp") != Some(fingerprint) {
+                continue;
+            }
+            let Some(ip) = preferred_address(info.get_addresses().iter()) else {
+                continue;
+            };
+            let peer = Peer {
+                name: info
+                    .get_fullname()
+                    .trim_end_matches(SERVICE_TYPE)
+                    .trim_end_matches('.')
+                    .to_string(),
+                host: ip.to_string(),
+                port: info.get_port(),
+                fingerprint: fingerprint.to_string(),
+                id: info.get_property_val_str("id").map(str::to_string),
+            };
+            if !peers.contains(&peer) {
+                peers.push(peer);
+            }
+        }
+
+        self.daemon.stop_browse(SERVICE_TYPE)?;
+        Ok(peers)
+    }
+}
+
+impl Drop for Discovery {
+    fn drop(&mut self) {
+        let _ = self.daemon.shutdown();
+    }
+}
+
+/// IPv4 предпочтительнее: такие адреса проще хранить в `connections`
+fn preferred_address<'a>(addresses: impl Iterator<Item = &'a IpAddr>) -> Option<IpAddr> {
+    let addresses: Vec<&IpAddr> = addresses.collect();
+    addresses
+        .iter()
+        .find(|ip| ip.is_ipv4())
+        .or_else(|| addresses.first())
+        .map(|ip| **ip)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mdns_sd::IfKind;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    fn loopback_discovery(name: &str) -> Discovery {
+        let discovery = Discovery {
+            daemon: ServiceDaemon::new().unwrap(),
+            name: name.to_string(),
+        };
+        discovery.daemon.disable_interface(IfKind::All).unwrap();
+        discovery
+            .daemon
+            .enable_interface(IfKind::LoopbackV4)
+            .unwrap();
+        discovery.daemon.set_multicast_loop_v4(true).unwrap();
+        discovery
+    }
+
+    #[tokio::test]
+    async fn discover_on_loopback() {
+        let server = loopback_discovery("bufsy-test-server");
+        server
+            .advertise(9311, "00aa11bb22cc33dd", "5e77e2d1d1ce1d00")
+            .unwrap();
+
+        let client = loopback_discovery("bufsy-test-client");
+        let peers = client
+            .browse(Duration::from_secs(3), "00aa11bb22cc33dd")
+            .await
+            .unwrap();
+
+        assert_eq!(
+            peers,
+            vec![Peer {
+                name: "bufsy-test-server".to_string(),
+                host: "127.0.0.1".to_string(),
+                port: 9311,
+                fingerprint: "00aa11bb22cc33dd".to_string(),
+                id: Some("5e77e2d1d1ce1d00".to_string()),
+            }]
+        );
+    }
+
+    #[test]
+    fn preferred_address_v4() {
+        let v6 = IpAddr::V6(Ipv6Addr::LOCALHOST);
+        let v4 = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        assert_eq!(preferred_address([v6, v4].iter()), Some(v4));
+        assert_eq!(preferred_address([v6].iter()), Some(v6));
+        assert_eq!(preferred_address([].iter()), None);
+    }
+}
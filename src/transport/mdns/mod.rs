@@ -0,0 +1,3 @@
+pub mod discovery;
+
+pub use discovery::Discovery;
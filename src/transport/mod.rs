@@ -1,5 +1,13 @@
+pub mod address;
 pub mod axum;
+pub mod client;
+pub mod control;
+pub mod error;
+pub mod mdns;
+pub mod pull;
 pub mod server;
+pub mod status;
+pub mod systemd;
 // pub mod udp;
 
 pub use self::server::Backend;
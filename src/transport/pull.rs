@@ -0,0 +1,119 @@
+use super::{address::Address, client::http_error};
+use crate::{
+    app::{
+        envelope::{Envelope, Kind},
+        history::Item,
+    },
+    config::Settings,
+};
+use anyhow::{Result, anyhow};
+use reqwest::{StatusCode, header};
+use serde::de::DeserializeOwned;
+
+/// Последняя запись истории устройства `peer`
+pub async fn latest(client: &reqwest::Client, peer: &str, config: &Settings) -> Result<Item> {
+    get(client, peer, "/latest", config).await
+}
+
+/// Записи `peer` с id больше `since`, от старых к новым
+pub async fn history(
+    client: &reqwest::Client,
+    peer: &str,
+    config: &Settings,
+    since: u64,
+) -> Result<Vec<Item>> {
+    get(client, peer, &format!("/history?since={}", since), config).await
+}
+
+/// GET с одноразовым токеном в `Authorization`: внутри зашифрован путь запроса, чтобы токен
+/// не подошёл к другому маршруту. Ответ зашифрован тем же ключом.
+async fn get<T: DeserializeOwned>(
+    client: &reqwest::Client,
+    peer: &str,
+    path: &str,
+    config: &Settings,
+) -> Result<T> {
+    let token = Envelope {
+        kind: Kind::Pull,
+        ..Envelope::new(config.server.port, &config.device_id)
+    }
+    .seal(path, &config.key)?;
+    let resp = client
+        .get(peer.parse::<Address>()?.url(path))
+        .header(header::AUTHORIZATION, format!("Bufsy {}", token))
+        .send()
+        .await?;
+    if !resp.status().is_success() {
+        let error = http_error(peer, resp).await;
+        if error.status == StatusCode::NOT_FOUND.as_u16() && error.code.is_none() {
+            return Err(anyhow!(
+                "{} does not share history: older bufsy or history disabled",
+                peer
+            ));
+        }
+        return Err(error.into());
+    }
+    let envelope = Envelope::parse(&resp.text().await?)?;
+    if envelope.kind != Kind::Pull {
+        return Err(anyhow!(
+            "{} answered with a {} message",
+            peer,
+            envelope.kind.as_str()
+        ));
+    }
+    Ok(serde_json::from_str(&envelope.open(&config.key)?)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        app::{
+            handler::Chain,
+            history::{Direction, History},
+        },
+        config::load_config::tests::test_load_config,
+        transport::{axum::ServerAXUM, client},
+    };
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn pull_latest() {
+        let config_dir = "test_dir/pull";
+        std::fs::create_dir_all(config_dir).unwrap();
+        let config = test_load_config();
+        let store = History::open(config_dir, &config);
+        store.clear().unwrap();
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let peer = listener.local_addr().unwrap().to_string();
+        let mut server = ServerAXUM::new("127.0.0.1", 0, Arc::new(Chain::new()), config.clone())
+            .with_listener(listener)
+            .with_history(config_dir);
+        tokio::spawn(async move { server.run().await });
+        let client = client::build(&Default::default()).unwrap();
+
+        let error = latest(&client, &peer, &config).await.unwrap_err();
+        assert!(error.to_string().contains("404"));
+
+        // id не начинаются с 1: счётчик переживает `clear`
+        let first = store
+            .record(Direction::Sent, "[::1]:9296", "first", false)
+            .unwrap()
+            .unwrap();
+        store
+            .record(Direction::Received, "10.0.0.2", "second", false)
+            .unwrap();
+        assert_eq!(
+            latest(&client, &peer, &config).await.unwrap().text,
+            "second"
+        );
+        let items = history(&client, &peer, &config, first).await.unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].id, first + 1);
+
+        let mut stranger = config.clone();
+        stranger.key = "0".repeat(64);
+        assert!(latest(&client, &peer, &stranger).await.is_err());
+    }
+}
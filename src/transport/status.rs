@@ -0,0 +1,168 @@
+use super::{
+    address::Address,
+    error::{ApiError, ErrorCode},
+};
+use crate::{
+    app::envelope::{Envelope, Kind},
+    config::Settings,
+};
+use anyhow::{Result, anyhow};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+use tokio::task::JoinSet;
+
+/// Что устройство сообщает о себе на `/status`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct NodeStatus {
+    pub version: String,
+    /// имя хоста
+    pub device: String,
+    pub device_id: String,
+    /// секунды с запуска `bufsy run`
+    pub uptime: u64,
+    pub fingerprint: String,
+}
+
+/// Результат опроса одного устройства
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct Probe {
+    /// `host:port`
+    pub peer: String,
+    pub reachable: bool,
+    pub latency_ms: u64,
+    /// `None` — не удалось выяснить (недоступно или старая версия без `/status`)
+    pub key_match: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<NodeStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Запрос `/status`: зашифрованное имя маршрута, ответ зашифрован тем же ключом.
+/// Так проверяется, что ключ совпадает в обе стороны.
+pub async fn probe(client: &reqwest::Client, peer: &str, config: &Settings) -> Probe {
+    let started = Instant::now();
+    let mut probe = Probe {
+        peer: peer.to_string(),
+        reachable: false,
+        latency_ms: 0,
+        key_match: None,
+        status: None,
+        error: None,
+    };
+    let result = request(client, peer, config).await;
+    probe.latency_ms = started.elapsed().as_millis() as u64;
+    match result {
+        Ok((code, body)) => {
+            probe.reachable = true;
+            match code {
+                StatusCode::OK => match open(&body, &config.key) {
+                    Ok(status) => {
+                        probe.key_match = Some(true);
+                        probe.status = Some(status);
+                    }
+                    Err(e) => {
+                        probe.key_match = Some(false);
+                        probe.error = Some(e.to_string());
+                    }
+                },
+                StatusCode::UNAUTHORIZED if is_expired(&body) => {
+                    probe.error = Some("clocks differ by more than 5 minutes".to_string())
+                }
+                StatusCode::UNAUTHORIZED => probe.key_match = Some(false),
+                StatusCode::NOT_FOUND => probe.error = Some("no /status, older bufsy".to_string()),
+                code => probe.error = Some(format!("answered {}", code)),
+            }
+        }
+        Err(e) => probe.error = Some(e.to_string()),
+    }
+    probe
+}
+
+async fn request(
+    client: &reqwest::Client,
+    peer: &str,
+    config: &Settings,
+) -> Result<(StatusCode, String)> {
+    let envelope = Envelope {
+        kind: Kind::Status,
+        ..Envelope::new(config.server.port, &config.device_id)
+    }
+    .seal("/status", &config.key)?;
+    let resp = client
+        .post(peer.parse::<Address>()?.url("/status"))
+        .body(envelope.to_string())
+        .send()
+        .await?;
+    Ok((resp.status(), resp.text().await?))
+}
+
+fn is_expired(body: &str) -> bool {
+    serde_json::from_str::<ApiError>(body).is_ok_and(|error| error.error == ErrorCode::Expired)
+}
+
+fn open(body: &str, key: &str) -> Result<NodeStatus> {
+    let envelope = Envelope::parse(body)?;
+    if envelope.kind != Kind::Status {
+        return Err(anyhow!("Not a status answer"));
+    }
+    Ok(serde_json::from_str(&envelope.open(key)?)?)
+}
+
+/// Опрашивает все `peers` одновременно, порядок совпадает с `peers`
+pub async fn probe_all(
+    client: &reqwest::Client,
+    peers: &[String],
+    config: &Settings,
+) -> Vec<Probe> {
+    let mut tasks = JoinSet::new();
+    for (index, peer) in peers.iter().enumerate() {
+        let client = client.clone();
+        let peer = peer.clone();
+        let config = config.clone();
+        tasks.spawn(async move { (index, probe(&client, &peer, &config).await) });
+    }
+    let mut probes: Vec<(usize, Probe)> = tasks.join_all().await;
+    probes.sort_by_key(|(index, _)| *index);
+    probes.into_iter().map(|(_, probe)| probe).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        app::handler::Chain,
+        config::load_config::tests::test_load_config,
+        transport::{axum::ServerAXUM, client},
+    };
+
+    #[tokio::test]
+    async fn probe_key_match() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let peer = listener.local_addr().unwrap().to_string();
+        let mut server = ServerAXUM::new(
+            "127.0.0.1",
+            0,
+            std::sync::Arc::new(Chain::new()),
+            test_load_config(),
+        )
+        .with_listener(listener);
+        tokio::spawn(async move { server.run().await });
+
+        let client = client::build(&Default::default()).unwrap();
+        let mut config = test_load_config();
+        let probes = probe_all(&client, &[peer.clone(), "127.0.0.1:1".to_string()], &config).await;
+        assert_eq!(probes[0].key_match, Some(true));
+        let status = probes[0].status.as_ref().unwrap();
+        assert_eq!(status.device_id, config.device_id);
+        assert_eq!(status.version, env!("CARGO_PKG_VERSION"));
+        assert!(!probes[1].reachable);
+
+        config.key = "0".repeat(64);
+        let probe = probe(&client, &peer, &config).await;
+        assert!(probe.reachable);
+        assert_eq!(probe.key_match, Some(false));
+        assert_eq!(probe.status, None);
+    }
+}
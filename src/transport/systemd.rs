@@ -0,0 +1,30 @@
+use anyhow::Result;
+use sd_notify::NotifyState;
+use std::{os::fd::FromRawFd, time::Duration};
+
+/// Сокет, открытый systemd (`bufsy.socket`), если процесс запущен через socket activation
+pub fn activated_listener() -> Result<Option<std::net::TcpListener>> {
+    let Some(fd) = sd_notify::listen_fds()?.next() else {
+        return Ok(None);
+    };
+    // SAFETY: systemd передаёт открытый слушающий сокет, владеет им теперь только этот процесс
+    Ok(Some(unsafe { std::net::TcpListener::from_raw_fd(fd) }))
+}
+
+/// Сообщает состояние systemd; без `NOTIFY_SOCKET` ничего не делает
+pub fn notify(state: NotifyState) {
+    let _ = sd_notify::notify(false, &[state]);
+}
+
+/// Пинги watchdog, если в юните задан `WatchdogSec`
+pub async fn watchdog_loop() {
+    let mut usec = 0;
+    if !sd_notify::watchdog_enabled(false, &mut usec) {
+        return;
+    }
+    let mut tick = tokio::time::interval(Duration::from_micros(usec / 2));
+    loop {
+        tick.tick().await;
+        notify(NotifyState::Watchdog);
+    }
+}